@@ -8,6 +8,10 @@ $ export TARGET_CC=x86_64-linux-musl-gcc
 $ cargo build --release --example actix --target x86_64-unknown-linux-musl
 $ cp target/x86_64-unknown-linux-musl/release/examples/actix ./bootstrap
 $ cat examples/alb_request.json | docker run --rm -v "$PWD":/var/task:ro,delegated -i -e DOCKER_LAMBDA_USE_STDIN=1 lambci/lambda:provided
+
+# Or serve it on http://127.0.0.1:3000, without docker
+$ cargo build --example actix
+$ cargo run --features cli --bin lambda-http-interop-serve -- --source alb target/debug/examples/actix
 */
 #[tokio::main]
 async fn main() {
@@ -1,11 +1,17 @@
-use actix_web::web::Bytes;
+use crate::connection::ConnectionInfo;
+use crate::convert::{ActixBody, FromLambdaBody, IntoLambdaBody};
+use crate::error::Renderer;
+use crate::init::{ColdStart, InitDuration};
+use crate::path::{prefix_location, BasePath};
+use crate::route::MatchedRoute;
 use actix_web::HttpMessage;
-use lambda_http::Body;
 use std::future::Future;
 use std::marker::PhantomData;
 use std::pin::Pin;
-use std::sync::Arc;
 use std::task::{Context as TaskContext, Poll};
+use std::time::Instant;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::PollSender;
 
 pub async fn run<F, I, S, B>(factory: F) -> Result<(), lambda_http::Error>
 where
@@ -15,21 +21,88 @@ where
     S::Error: Into<actix_web::Error>,
     S::InitError: std::fmt::Debug,
     S::Response: Into<actix_http::Response<B>>,
-    B: actix_web::body::MessageBody + Unpin,
+    B: actix_web::body::MessageBody + Unpin + 'static,
 {
-    lambda_http::run(service(factory).await).await
+    lambda_http::run(service(factory).await?).await
 }
 
-pub struct ActixTowerService<'a, S, B>
-where
-    S: actix_service::Service<actix_http::Request> + 'a,
-{
-    service: Arc<S>,
-    _phantom_b: PhantomData<B>,
-    _phantom_a: PhantomData<&'a ()>,
+type LambdaResult = Result<lambda_http::Response<lambda_http::Body>, lambda_http::Error>;
+
+type Invocation = (lambda_http::Request, oneshot::Sender<LambdaResult>, Trace);
+
+/// Span of the invocation on the lambda side, with the subscriber it belongs to, so
+/// that events of the actix application are recorded inside of it.
+#[cfg(feature = "tracing")]
+type Trace = (tracing::Span, tracing::Dispatch);
+#[cfg(not(feature = "tracing"))]
+type Trace = ();
+
+#[cfg(feature = "tracing")]
+fn current_trace() -> Trace {
+    let dispatch = tracing::dispatcher::get_default(tracing::Dispatch::clone);
+
+    (tracing::Span::current(), dispatch)
+}
+
+#[cfg(not(feature = "tracing"))]
+fn current_trace() -> Trace {}
+
+#[cfg(feature = "tracing")]
+fn in_trace<F: Future>(fut: F, (span, dispatch): Trace) -> impl Future<Output = F::Output> {
+    use tracing::instrument::{Instrument, WithSubscriber};
+
+    fut.instrument(span).with_subscriber(dispatch)
 }
 
-pub async fn service<'a, F, I, S, B>(factory: F) -> ActixTowerService<'a, S::Service, B>
+#[cfg(not(feature = "tracing"))]
+fn in_trace<F: Future>(fut: F, _trace: Trace) -> F {
+    fut
+}
+
+type BodyFuture = Pin<Box<dyn Future<Output = Result<lambda_http::Body, lambda_http::Error>>>>;
+
+/// A [`tower::Service`] forwarding requests to an actix service.
+///
+/// Actix services and their futures are `!Send`, whereas the lambda runtime requires
+/// its handler to be `Send`. To bridge the two, the actix service lives on a dedicated
+/// worker thread and invocations are exchanged with it over channels.
+///
+/// That thread runs an actix [`System`](actix_web::rt::System), the same way an
+/// `HttpServer` would, so applications relying on `actix_rt::spawn`, [`Arbiter`]s
+/// or actors behave as they would outside of lambda.
+///
+/// [`Arbiter`]: https://docs.rs/actix-rt/2/actix_rt/struct.Arbiter.html
+///
+/// The channel holds a single invocation, which the worker only picks up once the
+/// actix service is ready, so backpressure applied by the service (or one of its
+/// middleware) is observed through [`poll_ready`](tower::Service::poll_ready).
+///
+/// The actix service is created by [`service`], during the init phase of the lambda,
+/// which fails if the service cannot be created.
+///
+/// Only the connection info reflects the event: the `Host` and `X-Forwarded-*`
+/// headers are completed from it, so that `HttpRequest::connection_info` and
+/// `url_for` give the host, scheme and client address of each request, as behind a
+/// reverse proxy. The [`AppConfig`](actix_web::dev::AppConfig) is shared by every
+/// request and can only be created by actix-web, so it keeps its default `host`,
+/// `secure` and `local_addr`.
+pub struct ActixTowerService {
+    sender: PollSender<Invocation>,
+    // Whether `poll_ready` reserved a slot in the channel for the next call
+    reserved: bool,
+    base_path: BasePath,
+}
+
+impl ActixTowerService {
+    /// Remove a prefix (API Gateway stage, custom domain base path) from the request
+    /// path before it reaches the actix router, and add it back to redirections.
+    pub fn with_base_path(mut self, base_path: BasePath) -> Self {
+        self.base_path = base_path;
+        self
+    }
+}
+
+pub async fn service<F, I, S, B>(factory: F) -> Result<ActixTowerService, lambda_http::Error>
 where
     F: Fn() -> I + Send + Clone + 'static,
     I: actix_service::IntoServiceFactory<S, actix_http::Request>,
@@ -37,120 +110,266 @@ where
     S::Error: Into<actix_web::Error>,
     S::InitError: std::fmt::Debug,
     S::Response: Into<actix_http::Response<B>>,
-    S::Service: 'a,
-    B: actix_web::body::MessageBody,
+    B: actix_web::body::MessageBody + Unpin + 'static,
 {
-    let sf = factory().into_factory();
+    let (sender, receiver) = mpsc::channel(1);
+    let (created, init) = oneshot::channel();
 
-    let service = sf
-        .new_service(actix_web::dev::AppConfig::default())
-        .await
-        .unwrap(); // TODO return instead of unwraping
+    std::thread::Builder::new()
+        .name("actix-worker".to_string())
+        .spawn(move || {
+            // Running inside a System gives the application access to everything actix
+            // expects from its server: `actix_rt::spawn`, arbiters and actors.
+            actix_web::rt::System::new().block_on(async move {
+                // Created now rather than with the first invocation, so that it happens
+                // during the init phase of the lambda and its failures fail the init.
+                // Connection info comes from the headers of every request instead.
+                let started = Instant::now();
+                let sf = factory().into_factory();
 
-    let service = std::sync::Arc::new(service);
+                match sf.new_service(actix_web::dev::AppConfig::default()).await {
+                    Ok(service) => {
+                        let _ = created.send(Ok(()));
+                        worker(service, InitDuration(started.elapsed()), receiver).await;
+                    }
+                    Err(e) => {
+                        let message = format!("actix service cannot be created: {:?}", e);
+                        let _ = created.send(Err(lambda_http::Error::from(message)));
+                    }
+                }
+            });
+        })?;
 
-    ActixTowerService {
-        service,
-        _phantom_a: PhantomData,
-        _phantom_b: PhantomData,
-    }
+    init.await
+        .map_err(|_| lambda_http::Error::from("actix worker has stopped"))??;
+
+    Ok(ActixTowerService {
+        sender: PollSender::new(sender),
+        reserved: false,
+        base_path: BasePath::default(),
+    })
 }
 
-impl<'a, S, B> tower::Service<lambda_http::Request> for ActixTowerService<'a, S, B>
-where
-    S: actix_service::Service<actix_http::Request> + 'a,
-    S::Response: Into<actix_http::Response<B>>,
+/// Feed the invocations received from the lambda runtime to the actix service.
+///
+/// This runs on the actix worker thread, where `!Send` values are allowed.
+async fn worker<S, B>(
+    service: S,
+    init_duration: InitDuration,
+    mut invocations: mpsc::Receiver<Invocation>,
+) where
+    S: actix_service::Service<actix_http::Request>,
     S::Error: Into<actix_web::Error>,
-    B: actix_web::body::MessageBody + Unpin,
+    S::Response: Into<actix_http::Response<B>>,
+    B: actix_web::body::MessageBody + Unpin + 'static,
 {
+    loop {
+        // Waiting for the service before taking the next invocation leaves it in the
+        // channel, which is then full and makes `poll_ready` wait as well
+        let ready = std::future::poll_fn(|cx| service.poll_ready(cx)).await;
+
+        let (mut req, respond_to, trace) = match invocations.recv().await {
+            Some(invocation) => invocation,
+            None => break,
+        };
+        let info = ConnectionInfo::from_request(&req);
+
+        // Errors here are not tied to a request, so there is no response to render them in
+        if let Err(err) = ready {
+            let e: actix_web::Error = err.into();
+            let _ = respond_to.send(Err(e.to_string().into()));
+            continue;
+        }
+
+        let failure = req.extensions_mut().remove::<Renderer>().map(|renderer| {
+            let request_id = req
+                .extensions()
+                .get::<lambda_runtime::Context>()
+                .map(|context| context.request_id.clone())
+                .filter(|id| !id.is_empty());
+
+            (renderer, request_id)
+        });
+
+        let actix_req = http_to_actix_request(req, &info);
+        actix_req.extensions_mut().insert(init_duration);
+
+        // Calling the service runs middleware, which is part of the invocation as well
+        let response = async {
+            TransformResponse::WaitResponse {
+                fut: Box::pin(service.call(actix_req)),
+                failure,
+                _phantom: PhantomData,
+            }
+            .await
+        };
+
+        // The lambda side may have given up on this invocation, nothing to do then
+        let _ = respond_to.send(in_trace(response, trace).await);
+    }
+}
+
+impl tower::Service<lambda_http::Request> for ActixTowerService {
     type Response = lambda_http::Response<lambda_http::Body>;
     type Error = lambda_http::Error;
 
-    type Future = TransformResponse<'a, S::Response, B, S::Error>;
+    type Future = Pin<Box<dyn Future<Output = LambdaResult> + Send>>;
 
     fn poll_ready(
         &mut self,
-        _cx: &mut std::task::Context<'_>,
+        cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+        let ready = self
+            .sender
+            .poll_reserve(cx)
+            .map_err(|_| lambda_http::Error::from("actix worker has stopped"));
+        self.reserved = matches!(ready, Poll::Ready(Ok(())));
+
+        ready
     }
 
-    fn call(&mut self, req: lambda_http::Request) -> Self::Future {
-        let actix_req = http_to_actix_request(req);
-        let fut = Box::pin(self.service.call(actix_req));
+    fn call(&mut self, mut req: lambda_http::Request) -> Self::Future {
+        let prefix = self.base_path.strip(&mut req);
+        // Flagged in the order of the calls, before the request waits for the worker
+        ColdStart::of(&mut req);
 
-        TransformResponse {
-            fut,
-            _phantom: PhantomData,
-        }
+        let (tx, rx) = oneshot::channel();
+        let invocation = (req, tx, current_trace());
+
+        // `lambda_http::run` does not forward `poll_ready` to its handler, in which case
+        // no slot was reserved and the invocation waits for one instead
+        let waiting = if std::mem::take(&mut self.reserved) {
+            self.sender.send_item(invocation).map(|()| None)
+        } else {
+            Ok(Some((self.sender.get_ref().cloned(), invocation)))
+        };
+
+        Box::pin(async move {
+            let stopped = || lambda_http::Error::from("actix worker has stopped");
+
+            if let Some((sender, invocation)) = waiting.map_err(|_| stopped())? {
+                let sender = sender.ok_or_else(stopped)?;
+                sender.send(invocation).await.map_err(|_| stopped())?;
+            }
+
+            let mut response = rx
+                .await
+                .map_err(|_| lambda_http::Error::from("actix worker dropped the invocation"))??;
+
+            if let Some(prefix) = prefix {
+                prefix_location(&prefix, response.headers_mut());
+            }
+
+            Ok(response)
+        })
     }
 }
 
-/// Future that will convert an [`actix_http::Response`] into an actual [`lambda_http::Response`]
-///
-/// This is used by the `ActixTowerService` wrapper and is completely internal to the `service` function.
-#[doc(hidden)]
-pub struct TransformResponse<'a, R, B, E> {
-    fut: Pin<Box<dyn Future<Output = Result<R, E>> + 'a>>,
-    _phantom: PhantomData<B>,
+pin_project_lite::pin_project! {
+    /// Future that will convert an [`actix_http::Response`] into an actual [`lambda_http::Response`]
+    ///
+    /// This runs on the actix worker thread and is completely internal to the `service` function.
+    #[project = TransformProj]
+    #[doc(hidden)]
+    pub enum TransformResponse<'a, R, B, E> {
+        WaitResponse {
+            fut: Pin<Box<dyn Future<Output = Result<R, E>> + 'a>>,
+            // Renderer of the errors of the service, with the request id to render them with
+            failure: Option<(Renderer, Option<String>)>,
+            _phantom: PhantomData<B>,
+        },
+        WaitBody {
+            builder: Option<http::response::Builder>,
+            body: BodyFuture,
+        }
+    }
 }
 
 impl<'a, R, E, B> Future for TransformResponse<'a, R, B, E>
 where
     R: Into<actix_http::Response<B>>,
     E: Into<actix_web::Error>,
-    B: actix_web::body::MessageBody + Unpin,
+    B: actix_web::body::MessageBody + Unpin + 'static,
 {
     type Output = Result<lambda_http::Response<lambda_http::Body>, lambda_http::Error>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Self::Output> {
-        match self.fut.as_mut().poll(cx) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(result) => {
-                let r = match result {
-                    Ok(r) => actix_to_http_response(r.into()),
-                    Err(err) => {
-                        let e: actix_web::Error = err.into();
-
-                        actix_to_http_response(e.error_response().into())
-                    }
-                };
+        match self.as_mut().project() {
+            TransformProj::WaitResponse { fut, failure, .. } => match fut.as_mut().poll(cx) {
+                Poll::Pending => Poll::Pending,
+                Poll::Ready(result) => {
+                    let (builder, body) = match result {
+                        Ok(r) => actix_to_http_response(r.into()),
+                        Err(err) => {
+                            let e: actix_web::Error = err.into();
 
-                Poll::Ready(Ok(r))
-            }
+                            if let Some((renderer, request_id)) = failure.take() {
+                                // actix errors are not Send, only their message can be kept
+                                let status = e.as_response_error().status_code();
+                                let error = lambda_http::Error::from(e.to_string());
+
+                                return Poll::Ready(Ok(renderer.render_framework_error(
+                                    status,
+                                    error.as_ref(),
+                                    request_id.as_deref(),
+                                )));
+                            }
+
+                            let res: actix_http::Response<actix_web::body::BoxBody> =
+                                e.error_response().into();
+
+                            actix_to_http_response(res)
+                        }
+                    };
+
+                    // We got the response head, switching to next polling phase: getting the body
+                    self.set(TransformResponse::WaitBody {
+                        builder: Some(builder),
+                        body,
+                    });
+
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            },
+            TransformProj::WaitBody { builder, body } => match body.as_mut().poll(cx) {
+                Poll::Pending => Poll::Pending,
+                Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+                Poll::Ready(Ok(body)) => {
+                    let builder = builder.take().expect("builder cannot be None");
+
+                    Poll::Ready(builder.body(body).map_err(Into::into))
+                }
+            },
         }
     }
 }
 
-fn http_to_actix_request(req: lambda_http::Request) -> actix_http::Request {
+fn http_to_actix_request(
+    mut req: lambda_http::Request,
+    info: &ConnectionInfo,
+) -> actix_http::Request {
+    // Routers and extractors expect the query string to be part of the uri
+    crate::uri::normalize(&mut req, info);
+    crate::xray::propagate(&mut req);
+
     let (
         http::request::Parts {
             method,
             uri,
             version,
-            headers,
+            mut headers,
             mut extensions,
             ..
         },
         body,
     ) = req.into_parts();
 
-    use actix_http::{BoxedPayloadStream, Payload};
-
     // We start by transforming the lambda request body into an actix one.
-    let payload: Payload<BoxedPayloadStream> = {
-        let (_, mut payload) = actix_http::h1::Payload::create(true);
-
-        let b = match body {
-            lambda_http::Body::Empty => Bytes::default(),
-            lambda_http::Body::Text(s) => Bytes::from(s),
-            lambda_http::Body::Binary(b) => Bytes::from(b),
-        };
+    let payload = actix_http::Payload::from_lambda_body(body);
 
-        payload.unread_data(b);
-
-        payload.into()
-    };
+    // Actix derives the connection info (scheme, host, real ip) from these headers
+    info.complete_headers(&mut headers);
 
     // Then we move the Parts from http to actix
     let mut actix_request = actix_http::Request::with_payload(payload);
@@ -159,9 +378,10 @@ fn http_to_actix_request(req: lambda_http::Request) -> actix_http::Request {
     head.uri = uri;
     head.version = version;
     head.headers = headers.into();
+    head.peer_addr = info.peer_addr();
 
     // And finally set some extensions. We ignore the query/params/stage extensions as
-    // they should already be present in the uri.
+    // they are now part of the uri.
 
     let mut r_ext = actix_request.extensions_mut();
 
@@ -173,52 +393,828 @@ fn http_to_actix_request(req: lambda_http::Request) -> actix_http::Request {
         r_ext.insert(req_context);
     }
 
+    if let Some(cold_start) = extensions.remove::<ColdStart>() {
+        r_ext.insert(cold_start);
+    }
+
     // We are done inserting extensions, release reference to it
     drop(r_ext);
 
     actix_request
 }
 
-fn actix_to_http_response<B: actix_web::body::MessageBody>(
-    res: actix_http::Response<B>,
-) -> lambda_http::Response<lambda_http::Body> {
-    // The ResponseHead/Parts is gonna be relatively simple
-    // The body is going to be way more interesting, mostly
-    // because actix MessageBody can be a Stream.
-    // Perhaps let the stream on the side for a first pass
-    // and come back to it later down the road ?
-    // Do note that AWS lambda do not support chunked/stream
-    // responses, so we could accumulate the content of the
-    // stream in memory to build the correct lambda_http::Body
-
+/// Split an actix response into the head of the lambda response and the future
+/// accumulating its body.
+///
+/// Do note that AWS lambda do not support chunked/stream responses, so the content
+/// of streamed bodies gets accumulated in memory.
+fn actix_to_http_response<B>(res: actix_http::Response<B>) -> (http::response::Builder, BodyFuture)
+where
+    B: actix_web::body::MessageBody + 'static,
+{
     let (head, body) = res.into_parts();
 
     let mut builder = lambda_http::Response::builder().status(head.status());
 
+    if let Some(route) = head.extensions().get::<MatchedRoute>() {
+        builder = builder.extension(route.clone());
+    }
+
     // TODO Consider using head.headers_mut().drain() to avoid cloning the headers
     for (name, value) in head.headers() {
         builder = builder.header(name, value);
     }
 
-    let b = match body.size() {
-        actix_http::body::BodySize::None => Body::Empty,
-        _ => {
-            // TODO Do we need to set the correct Content-Length header ?
+    // TODO Do we need to set the correct Content-Length header ?
+    (builder, ActixBody(body).into_lambda_body())
+}
+
+/// Middleware recording the pattern of the matched route in the response, as a
+/// [`MatchedRoute`], for the layers wrapping the adapter.
+///
+/// ```ignore
+/// App::new()
+///     .wrap(aws_lambda_http_interop::actix::RecordRoute)
+///     .route("/users/{id}", web::get().to(user))
+/// ```
+pub struct RecordRoute;
+
+impl<S, B> actix_service::Transform<S, actix_web::dev::ServiceRequest> for RecordRoute
+where
+    S: actix_service::Service<
+        actix_web::dev::ServiceRequest,
+        Response = actix_web::dev::ServiceResponse<B>,
+        Error = actix_web::Error,
+    >,
+    S::Future: 'static,
+{
+    type Response = actix_web::dev::ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RecordRouteMiddleware<S>;
+    type InitError = ();
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(RecordRouteMiddleware { service }))
+    }
+}
+
+#[doc(hidden)]
+pub struct RecordRouteMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> actix_service::Service<actix_web::dev::ServiceRequest> for RecordRouteMiddleware<S>
+where
+    S: actix_service::Service<
+        actix_web::dev::ServiceRequest,
+        Response = actix_web::dev::ServiceResponse<B>,
+        Error = actix_web::Error,
+    >,
+    S::Future: 'static,
+{
+    type Response = actix_web::dev::ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    actix_service::forward_ready!(service);
+
+    fn call(&self, req: actix_web::dev::ServiceRequest) -> Self::Future {
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+
+            // The request is only routed once the inner service has been called
+            if let Some(pattern) = res.request().match_pattern() {
+                res.response_mut()
+                    .extensions_mut()
+                    .insert(MatchedRoute(pattern));
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+type EventInvocation<A, B> = (
+    lambda_runtime::LambdaEvent<A>,
+    oneshot::Sender<Result<B, lambda_runtime::Error>>,
+);
+
+/// A [`tower::Service`] forwarding raw lambda events to an actix service.
+///
+/// This is the counterpart of [`ActixTowerService`] for functions which are not behind
+/// an HTTP event source: the actix service lives on its own worker thread, inside an
+/// actix [`System`](actix_web::rt::System), so it can be `!Send` and be wrapped in
+/// actix middleware.
+pub struct ActixEventService<A, B> {
+    sender: PollSender<EventInvocation<A, B>>,
+}
+
+/// Create a service for `lambda_runtime::run` out of an actix service factory.
+///
+/// The factory is called on the worker thread, meaning only the closure needs to be
+/// `Send`, not the service it creates. Like [`service`], the actix service is created
+/// during the init phase of the lambda, which fails if the service cannot be created.
+pub async fn event_service<F, I, S, A, B>(
+    factory: F,
+) -> Result<ActixEventService<A, B>, lambda_runtime::Error>
+where
+    F: Fn() -> I + Send + 'static,
+    I: actix_service::IntoServiceFactory<S, lambda_runtime::LambdaEvent<A>>,
+    S: actix_service::ServiceFactory<lambda_runtime::LambdaEvent<A>, Config = (), Response = B>,
+    S::Error: std::fmt::Display,
+    S::InitError: std::fmt::Debug,
+    A: Send + 'static,
+    B: Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(1);
+    let (created, init) = oneshot::channel();
 
-            match body.try_into_bytes() {
-                Ok(bytes) => {
-                    // TODO how do we decide between Body::String and Body::Binary ?
-                    Body::Binary(bytes.to_vec())
+    std::thread::Builder::new()
+        .name("actix-worker".to_string())
+        .spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                match factory().into_factory().new_service(()).await {
+                    Ok(service) => {
+                        let _ = created.send(Ok(()));
+                        event_worker(service, receiver).await;
+                    }
+                    Err(e) => {
+                        let message = format!("actix service cannot be created: {:?}", e);
+                        let _ = created.send(Err(lambda_runtime::Error::from(message)));
+                    }
                 }
-                Err(_body) => {
-                    // TODO poll the body and accumulate its content here
-                    todo!("We do not support streamed response yet")
+            });
+        })?;
+
+    init.await
+        .map_err(|_| lambda_runtime::Error::from("actix worker has stopped"))??;
+
+    Ok(ActixEventService {
+        sender: PollSender::new(sender),
+    })
+}
+
+async fn event_worker<S, A, B>(service: S, mut invocations: mpsc::Receiver<EventInvocation<A, B>>)
+where
+    S: actix_service::Service<lambda_runtime::LambdaEvent<A>, Response = B>,
+    S::Error: std::fmt::Display,
+{
+    while let Some((event, respond_to)) = invocations.recv().await {
+        let response = match std::future::poll_fn(|cx| service.poll_ready(cx)).await {
+            Ok(()) => service.call(event).await,
+            Err(e) => Err(e),
+        };
+
+        // Actix errors are not always Send, only their message crosses threads
+        let _ = respond_to.send(response.map_err(|e| e.to_string().into()));
+    }
+}
+
+impl<A, B> tower::Service<lambda_runtime::LambdaEvent<A>> for ActixEventService<A, B>
+where
+    A: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = B;
+    type Error = lambda_runtime::Error;
+
+    type Future = Pin<Box<dyn Future<Output = Result<B, lambda_runtime::Error>> + Send>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.sender
+            .poll_reserve(cx)
+            .map_err(|_| lambda_runtime::Error::from("actix worker has stopped"))
+    }
+
+    fn call(&mut self, event: lambda_runtime::LambdaEvent<A>) -> Self::Future {
+        let (tx, rx) = oneshot::channel();
+        let sent = self.sender.send_item((event, tx));
+
+        Box::pin(async move {
+            sent.map_err(|_| lambda_runtime::Error::from("actix worker has stopped"))?;
+
+            rx.await
+                .map_err(|_| lambda_runtime::Error::from("actix worker dropped the invocation"))?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::HttpResponse;
+    use std::cell::Cell;
+    use std::future::{ready, Ready};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tower::Service;
+
+    // An actix service which is not ready until the test says so, similar to what a
+    // rate limiting middleware would do.
+    struct SlowToStart {
+        started: Arc<AtomicBool>,
+    }
+
+    impl actix_service::Service<actix_http::Request> for SlowToStart {
+        type Response = HttpResponse;
+        type Error = actix_web::Error;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            if self.started.load(Ordering::SeqCst) {
+                Poll::Ready(Ok(()))
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+
+        fn call(&self, _req: actix_http::Request) -> Self::Future {
+            ready(Ok(HttpResponse::Ok().body("ready")))
+        }
+    }
+
+    fn request(uri: &str) -> lambda_http::Request {
+        http::Request::get(uri)
+            .body(lambda_http::Body::Empty)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn wait_for_actix_service_readiness() {
+        let started = Arc::new(AtomicBool::new(false));
+        let factory = {
+            let started = started.clone();
+            move || {
+                let started = started.clone();
+                actix_service::fn_factory_with_config(move |_: actix_web::dev::AppConfig| {
+                    let started = started.clone();
+                    async move { Ok::<_, ()>(SlowToStart { started }) }
+                })
+            }
+        };
+
+        let mut service = super::service(factory).await.unwrap();
+
+        // The channel has room for one invocation, which the worker leaves there
+        futures::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        let response = service.call(request("/"));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let ready = futures::future::poll_fn(|cx| Poll::Ready(service.poll_ready(cx))).await;
+        assert!(ready.is_pending());
+
+        started.store(true, Ordering::SeqCst);
+        let response = response.await.unwrap();
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(
+            response.into_body(),
+            lambda_http::Body::Binary("ready".as_bytes().to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn call_without_poll_ready() {
+        // Like `lambda_http::run`, which does not forward `poll_ready` to its handler
+        let factory = || {
+            actix_web::App::new().route(
+                "/hey",
+                actix_web::web::get().to(|| async { HttpResponse::Ok().body("Hey there!") }),
+            )
+        };
+
+        let mut service = super::service(factory).await.unwrap();
+
+        for _ in 0..2 {
+            let response = service.call(request("/hey")).await.unwrap();
+
+            assert_eq!(response.status(), http::StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn handle_actix_app_from_multi_threaded_runtime() {
+        let factory = || {
+            actix_web::App::new().route(
+                "/hey",
+                actix_web::web::get().to(|| async { HttpResponse::Ok().body("Hey there!") }),
+            )
+        };
+
+        let mut service = super::service(factory).await.unwrap();
+
+        // The lambda runtime requires Send futures, the whole point of the worker thread
+        let response = tokio::spawn(async move {
+            futures::future::poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .unwrap();
+            service.call(request("https://example.com/hey")).await
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(
+            response.into_body(),
+            lambda_http::Body::Binary("Hey there!".as_bytes().to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn derive_connection_info_from_event() {
+        async fn url(req: actix_web::HttpRequest) -> HttpResponse {
+            let info = req.connection_info();
+            let url = req.url_for_static("hey").unwrap();
+            let config = req.app_config();
+
+            HttpResponse::Ok().body(format!(
+                "{} {} {:?} {} {} {}",
+                info.scheme(),
+                info.realip_remote_addr().unwrap_or("-"),
+                req.peer_addr(),
+                url,
+                config.host(),
+                config.secure(),
+            ))
+        }
+
+        let factory = || {
+            actix_web::App::new().service(
+                actix_web::web::resource("/hey")
+                    .name("hey")
+                    .route(actix_web::web::get().to(url)),
+            )
+        };
+
+        let mut service = super::service(factory).await.unwrap();
+
+        let request = http::Request::get("/hey")
+            .header("host", "example.com")
+            .header("x-forwarded-for", "203.0.113.7")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+
+        futures::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+
+        assert_eq!(
+            response.into_body(),
+            lambda_http::Body::Binary(
+                "https 203.0.113.7 Some(203.0.113.7:0) https://example.com/hey localhost:8080 false"
+                    .as_bytes()
+                    .to_vec()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn strip_base_path_from_request() {
+        use crate::path::BasePath;
+
+        let factory = || {
+            actix_web::App::new().route(
+                "/login",
+                actix_web::web::get().to(|| async {
+                    HttpResponse::Found()
+                        .insert_header(("location", "/welcome"))
+                        .finish()
+                }),
+            )
+        };
+
+        let mut service = super::service(factory)
+            .await
+            .unwrap()
+            .with_base_path(BasePath::Prefix("/api".to_string()));
+
+        futures::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        let response = service.call(request("/api/login")).await.unwrap();
+
+        assert_eq!(response.status(), http::StatusCode::FOUND);
+        assert_eq!(response.headers()["location"], "/api/welcome");
+    }
+
+    #[tokio::test]
+    async fn record_matched_route() {
+        use crate::route::MatchedRoute;
+
+        let factory = || {
+            actix_web::App::new().wrap(super::RecordRoute).route(
+                "/users/{id}",
+                actix_web::web::get().to(|| async { HttpResponse::Ok().finish() }),
+            )
+        };
+
+        let mut service = super::service(factory).await.unwrap();
+
+        futures::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        let response = service.call(request("/users/42")).await.unwrap();
+
+        assert_eq!(
+            response.extensions().get::<MatchedRoute>(),
+            Some(&MatchedRoute("/users/{id}".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_init_when_app_cannot_be_created() {
+        let factory = || {
+            actix_web::App::new()
+                .data_factory(|| async { Err::<u32, _>("cannot connect to the database") })
+                .route("/", actix_web::web::get().to(HttpResponse::Ok))
+        };
+
+        let error = super::service(factory).await.err().unwrap();
+
+        assert!(error
+            .to_string()
+            .starts_with("actix service cannot be created"));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn log_handler_events_inside_invocation_span() {
+        use std::cell::RefCell;
+        use std::collections::HashMap;
+        use std::sync::Mutex;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+        use tracing_core::span::Current;
+
+        thread_local! {
+            static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(vec![]) };
+        }
+
+        #[derive(Default)]
+        struct Fields(HashMap<String, String>);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0
+                    .insert(field.name().to_string(), format!("{:?}", value));
+            }
+
+            fn record_str(&mut self, field: &Field, value: &str) {
+                self.0.insert(field.name().to_string(), value.to_string());
+            }
+        }
+
+        /// Metadata and request id of every span.
+        type Spans = Vec<(&'static Metadata<'static>, Option<String>)>;
+        /// Message of every event, with the request id of its span.
+        type Messages = Vec<(String, Option<String>)>;
+
+        #[derive(Clone, Default)]
+        struct Events {
+            spans: Arc<Mutex<Spans>>,
+            events: Arc<Mutex<Messages>>,
+        }
+
+        impl Subscriber for Events {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = Fields::default();
+                span.record(&mut fields);
+
+                let mut spans = self.spans.lock().unwrap();
+                spans.push((span.metadata(), fields.0.remove("request_id")));
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Fields::default();
+                event.record(&mut fields);
+
+                let span = ENTERED.with(|entered| entered.borrow().last().copied());
+                let request_id =
+                    span.and_then(|id| self.spans.lock().unwrap()[id as usize - 1].1.clone());
+                let message = fields.0.remove("message").unwrap_or_default();
+                self.events.lock().unwrap().push((message, request_id));
+            }
+
+            fn enter(&self, span: &Id) {
+                ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+            }
+
+            fn exit(&self, _span: &Id) {
+                ENTERED.with(|entered| entered.borrow_mut().pop());
+            }
+
+            fn current_span(&self) -> Current {
+                match ENTERED.with(|entered| entered.borrow().last().copied()) {
+                    Some(id) => {
+                        let metadata = self.spans.lock().unwrap()[id as usize - 1].0;
+                        Current::new(Id::from_u64(id), metadata)
+                    }
+                    None => Current::none(),
                 }
             }
         }
-    };
 
-    builder
-        .body(b)
-        .expect("actix to http response conversion should not fail")
+        let events = Events::default();
+        let _guard = tracing::subscriber::set_default(events.clone());
+
+        let factory = || {
+            actix_web::App::new().route(
+                "/",
+                actix_web::web::get().to(|| async {
+                    tracing::info!("handling");
+                    HttpResponse::Ok().finish()
+                }),
+            )
+        };
+
+        let service = super::service(factory).await.unwrap();
+        let mut service = tower::layer::Layer::layer(&crate::trace::TraceLayer::new(), service);
+
+        let mut request = request("/");
+        let mut context = lambda_runtime::Context::default();
+        context.request_id = "8476a536-e9f4-11e8-9739-2dfe598c3fcd".to_string();
+        request.extensions_mut().insert(context);
+
+        futures::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        service.call(request).await.unwrap();
+
+        assert_eq!(
+            *events.events.lock().unwrap(),
+            vec![(
+                "handling".to_string(),
+                Some("8476a536-e9f4-11e8-9739-2dfe598c3fcd".to_string())
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn flag_cold_start_once_per_process() {
+        use crate::init::{ColdStart, InitDuration};
+        use actix_web::web::ReqData;
+
+        let factory = || {
+            actix_web::App::new().route(
+                "/",
+                actix_web::web::get().to(
+                    |cold: ReqData<ColdStart>, init: Option<ReqData<InitDuration>>| async move {
+                        assert!(init.is_some());
+                        cold.0.to_string()
+                    },
+                ),
+            )
+        };
+
+        let mut service = super::service(factory).await.unwrap();
+
+        // Other tests of this process may have handled the first request already, and
+        // an outer layer, like the metrics one, may have flagged the request before
+        for (flagged, expected) in [
+            (None, None),
+            (None, Some("false")),
+            (Some(true), Some("true")),
+        ] {
+            let mut request = request("/");
+            if let Some(cold) = flagged {
+                request.extensions_mut().insert(ColdStart(cold));
+            }
+
+            futures::future::poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .unwrap();
+            let response = service.call(request).await.unwrap();
+
+            if let Some(expected) = expected {
+                assert_eq!(
+                    response.into_body(),
+                    lambda_http::Body::Binary(expected.as_bytes().to_vec())
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn render_service_errors_with_renderer() {
+        use crate::error::{render_errors, ProblemJson};
+
+        let factory = || {
+            actix_web::App::new()
+                .wrap_fn(|_req, _srv| async {
+                    Err::<actix_web::dev::ServiceResponse, _>(actix_web::error::ErrorUnauthorized(
+                        "missing token",
+                    ))
+                })
+                .route("/", actix_web::web::get().to(HttpResponse::Ok))
+        };
+
+        let service = super::service(factory).await.unwrap();
+        let mut service = render_errors(
+            service,
+            ProblemJson {
+                expose_details: true,
+            },
+        );
+
+        futures::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        let response = service.call(request("/")).await.unwrap();
+
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/problem+json"
+        );
+        match response.into_body() {
+            lambda_http::Body::Text(text) => assert!(text.contains("missing token")),
+            other => panic!("unexpected body {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn run_actix_app_inside_a_system() {
+        async fn spawn_on_arbiter(
+            counter: actix_web::web::Data<std::sync::atomic::AtomicUsize>,
+        ) -> HttpResponse {
+            use std::sync::atomic::Ordering;
+
+            // Both of these would panic outside of an actix System
+            let arbiter = actix_web::rt::System::current().arbiter().clone();
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            arbiter.spawn(async move {
+                let _ = tx.send(());
+            });
+            rx.await.unwrap();
+
+            let count =
+                actix_web::rt::spawn(async move { counter.fetch_add(1, Ordering::SeqCst) + 1 })
+                    .await
+                    .unwrap();
+
+            HttpResponse::Ok().body(count.to_string())
+        }
+
+        let counter = actix_web::web::Data::new(std::sync::atomic::AtomicUsize::new(0));
+        let factory = move || {
+            actix_web::App::new()
+                .app_data(counter.clone())
+                .route("/", actix_web::web::get().to(spawn_on_arbiter))
+        };
+
+        let mut service = super::service(factory).await.unwrap();
+
+        for expected in ["1", "2"] {
+            futures::future::poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .unwrap();
+            let response = service.call(request("/")).await.unwrap();
+
+            assert_eq!(
+                response.into_body(),
+                lambda_http::Body::Binary(expected.as_bytes().to_vec())
+            );
+        }
+    }
+
+    // Adds a field to every response, standing for any actix middleware
+    struct Tag;
+
+    struct TagService<S>(S);
+
+    type Event = lambda_runtime::LambdaEvent<serde_json::Value>;
+
+    impl<S> actix_service::Transform<S, Event> for Tag
+    where
+        S: actix_service::Service<Event, Response = serde_json::Value>,
+        S::Future: 'static,
+    {
+        type Response = serde_json::Value;
+        type Error = S::Error;
+        type Transform = TagService<S>;
+        type InitError = ();
+        type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+        fn new_transform(&self, service: S) -> Self::Future {
+            ready(Ok(TagService(service)))
+        }
+    }
+
+    impl<S> actix_service::Service<Event> for TagService<S>
+    where
+        S: actix_service::Service<Event, Response = serde_json::Value>,
+        S::Future: 'static,
+    {
+        type Response = serde_json::Value;
+        type Error = S::Error;
+        type Future = std::pin::Pin<
+            Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>,
+        >;
+
+        fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.0.poll_ready(cx)
+        }
+
+        fn call(&self, event: Event) -> Self::Future {
+            let fut = self.0.call(event);
+
+            Box::pin(async move {
+                let mut response = fut.await?;
+                response["tagged"] = serde_json::json!(true);
+                Ok(response)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn fail_init_when_event_service_cannot_be_created() {
+        type Boxed = actix_service::boxed::BoxService<Event, serde_json::Value, String>;
+
+        let factory = || {
+            actix_service::fn_factory(|| async {
+                Err::<Boxed, _>("cannot connect to the database")
+            })
+        };
+
+        let error = super::event_service(factory).await.err().unwrap();
+
+        assert!(error
+            .to_string()
+            .starts_with("actix service cannot be created"));
+    }
+
+    #[tokio::test]
+    async fn forward_events_to_actix_service() {
+        use std::rc::Rc;
+
+        let factory = || {
+            // Not Send, which is fine as the service stays on the worker thread
+            let calls = Rc::new(Cell::new(0));
+
+            let handler = actix_service::fn_service(move |event: Event| {
+                calls.set(calls.get() + 1);
+                let calls = calls.get();
+
+                async move {
+                    match event.payload["name"].as_str() {
+                        Some(name) => Ok(serde_json::json!({ "hello": name, "calls": calls })),
+                        None => Err("name is missing"),
+                    }
+                }
+            });
+
+            actix_service::apply(Tag, handler)
+        };
+
+        let mut service = super::event_service(factory).await.unwrap();
+
+        let event = |payload| Event::new(payload, lambda_runtime::Context::default());
+
+        for calls in [1, 2] {
+            futures::future::poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .unwrap();
+            let response = service
+                .call(event(serde_json::json!({ "name": "Jane" })))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                response,
+                serde_json::json!({ "hello": "Jane", "calls": calls, "tagged": true })
+            );
+        }
+
+        futures::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        let error = service
+            .call(event(serde_json::json!({})))
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "name is missing");
+    }
 }
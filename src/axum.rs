@@ -1,16 +1,113 @@
-use axum::{routing::future::RouterFuture, Router};
+use crate::connection::ConnectionInfo;
+use crate::convert::{FromLambdaBody, IntoLambdaBody};
+use crate::init::{ColdStart, InitDuration};
+use crate::path::{prefix_location, BasePath};
+use crate::route::MatchedRoute;
+use axum::extract::MatchedPath;
+use axum::{body::BoxBody, extract::ConnectInfo, routing::future::RouterFuture, Router};
 use std::{
     future::Future,
     pin::Pin,
     task::{Context, Poll},
+    time::Instant,
 };
 
 pub struct AxumService {
     router: Router,
+    base_path: BasePath,
+    init_duration: Option<InitDuration>,
+}
+
+impl AxumService {
+    /// Remove a prefix (API Gateway stage, custom domain base path) from the request
+    /// path before it reaches the router, and add it back to redirections.
+    pub fn with_base_path(mut self, base_path: BasePath) -> Self {
+        self.base_path = base_path;
+        self
+    }
 }
 
 pub async fn service(router: Router) -> AxumService {
-    AxumService { router }
+    AxumService {
+        // Only routes know which of them matched, the router does not
+        router: router.route_layer(RecordRouteLayer),
+        base_path: BasePath::default(),
+        init_duration: None,
+    }
+}
+
+/// Like [`service`], with the router built by `build`, which is timed to give handlers
+/// an [`InitDuration`] along with the [`ColdStart`] extension.
+///
+/// ```ignore
+/// let service = axum::service_with(|| async {
+///     let pool = PgPool::connect(&url).await.unwrap();
+///     Router::new().route("/", get(root)).layer(Extension(pool))
+/// })
+/// .await;
+/// ```
+pub async fn service_with<F, Fut>(build: F) -> AxumService
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Router>,
+{
+    let started = Instant::now();
+    let router = build().await;
+    let init_duration = InitDuration(started.elapsed());
+
+    AxumService {
+        init_duration: Some(init_duration),
+        ..service(router).await
+    }
+}
+
+/// Copies the [`MatchedPath`] of the request to the response, as a [`MatchedRoute`].
+#[derive(Clone)]
+struct RecordRouteLayer;
+
+impl<S> tower::layer::Layer<S> for RecordRouteLayer {
+    type Service = RecordRoute<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        RecordRoute { service }
+    }
+}
+
+#[derive(Clone)]
+struct RecordRoute<S> {
+    service: S,
+}
+
+impl<S, B, ResBody> tower::Service<http::Request<B>> for RecordRoute<S>
+where
+    S: tower::Service<http::Request<B>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| MatchedRoute(path.as_str().to_string()));
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+
+            if let Some(route) = route {
+                res.extensions_mut().insert(route);
+            }
+
+            Ok(res)
+        })
+    }
 }
 
 impl tower::Service<lambda_http::Request> for AxumService {
@@ -28,15 +125,30 @@ impl tower::Service<lambda_http::Request> for AxumService {
             .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
     }
 
-    fn call(&mut self, req: lambda_http::Request) -> Self::Future {
-        let r = req.map(|b| match b {
-            lambda_http::Body::Empty => hyper::Body::empty(),
-            lambda_http::Body::Text(s) => s.into(),
-            lambda_http::Body::Binary(b) => b.into(),
-        });
+    fn call(&mut self, mut req: lambda_http::Request) -> Self::Future {
+        let location_prefix = self.base_path.strip(&mut req);
+        ColdStart::of(&mut req);
+        let info = ConnectionInfo::from_request(&req);
+        crate::uri::normalize(&mut req, &info);
+        crate::xray::propagate(&mut req);
+
+        let mut r = req.map(hyper::Body::from_lambda_body);
+
+        // Lets handlers and middleware use the `ConnectInfo<SocketAddr>` extractor
+        if let Some(addr) = info.peer_addr() {
+            r.extensions_mut().insert(ConnectInfo(addr));
+        }
+
+        if let Some(init_duration) = self.init_duration {
+            r.extensions_mut().insert(init_duration);
+        }
+
         let fut = Box::new(self.router.call(r));
 
-        TransformResponse::WaitResponse { fut }
+        TransformResponse::WaitResponse {
+            fut,
+            location_prefix,
+        }
     }
 }
 
@@ -49,10 +161,14 @@ pin_project_lite::pin_project! {
     #[project = TransformProj]
     #[doc(hidden)]
     pub enum TransformResponse {
-        WaitResponse{ #[pin] fut: Box<RouterFuture<hyper::Body>> },
+        WaitResponse {
+            #[pin]
+            fut: Box<RouterFuture<hyper::Body>>,
+            location_prefix: Option<String>,
+        },
         WaitBody {
             parts: Option<http::response::Parts>,
-            body: Pin<Box<dyn Future<Output = Result<axum::body::Bytes, axum::Error>>>>
+            body: <BoxBody as IntoLambdaBody>::Future,
         }
     }
 }
@@ -62,15 +178,27 @@ impl Future for TransformResponse {
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         match self.as_mut().project() {
-            TransformProj::WaitResponse { fut } => {
+            TransformProj::WaitResponse {
+                fut,
+                location_prefix,
+            } => {
                 match fut.poll(cx) {
                     Poll::Pending => Poll::Pending,
                     Poll::Ready(result) => {
-                        let response = result.unwrap(); // TODO
+                        // Routers cannot fail, errors are responses in axum
+                        let response = match result {
+                            Ok(response) => response,
+                            Err(infallible) => match infallible {},
+                        };
+
+                        let (mut parts, body) = response.into_parts();
+
+                        if let Some(prefix) = location_prefix {
+                            prefix_location(prefix, &mut parts.headers);
+                        }
 
-                        let (parts, body) = response.into_parts();
                         let parts = Some(parts);
-                        let body = Box::pin(hyper::body::to_bytes(body));
+                        let body = body.into_lambda_body();
 
                         // We got the response, switching to next polling phase: getting the body
                         self.set(TransformResponse::WaitBody { parts, body });
@@ -81,26 +209,17 @@ impl Future for TransformResponse {
                     }
                 }
             }
-            TransformProj::WaitBody { parts, body } => {
-                match body.as_mut().poll(cx) {
-                    Poll::Pending => Poll::Pending,
-                    Poll::Ready(body) => {
-                        let bytes = body.unwrap(); // TODO
-
-                        let body = if bytes.is_empty() {
-                            lambda_http::Body::Empty
-                        } else {
-                            lambda_http::Body::Binary(bytes.to_vec())
-                        };
+            TransformProj::WaitBody { parts, body } => match body.as_mut().poll(cx) {
+                Poll::Pending => Poll::Pending,
+                Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+                Poll::Ready(Ok(body)) => {
+                    let parts = parts.take().expect("parts cannot be None");
 
-                        let parts = parts.take().expect("parts cannot be None");
+                    let response = lambda_http::Response::from_parts(parts, body);
 
-                        let response = lambda_http::Response::from_parts(parts, body);
-
-                        Poll::Ready(Ok(response))
-                    }
+                    Poll::Ready(Ok(response))
                 }
-            }
+            },
         }
     }
 }
@@ -127,7 +246,10 @@ mod tests {
 
         let fut = Box::new(app.call(request));
 
-        let transform = super::TransformResponse::WaitResponse { fut };
+        let transform = super::TransformResponse::WaitResponse {
+            fut,
+            location_prefix: None,
+        };
 
         let res = transform.await;
 
@@ -137,9 +259,125 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn expose_source_ip_as_connect_info() {
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::get(|ConnectInfo(addr): ConnectInfo<SocketAddr>| async move {
+                addr.to_string()
+            }),
+        );
+        let mut service = super::service(router).await;
+
+        let request = http::Request::get("https://www.rust-lang.org/")
+            .header("x-forwarded-for", "72.21.198.66, 10.0.0.1")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+
+        let response = service.call(request).await.unwrap();
+
+        assert_eq!(
+            response.into_body(),
+            lambda_http::Body::Binary("72.21.198.66:0".as_bytes().to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn strip_base_path_from_request() {
+        use crate::path::BasePath;
+
+        let router = axum::Router::new().route(
+            "/login",
+            axum::routing::get(|| async {
+                axum::response::Redirect::to("/welcome".parse().unwrap())
+            }),
+        );
+        let mut service = super::service(router)
+            .await
+            .with_base_path(BasePath::Prefix("/api".to_string()));
+
+        let request = http::Request::get("https://www.rust-lang.org/api/login")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+
+        let response = service.call(request).await.unwrap();
+
+        assert_eq!(response.headers()["location"], "/api/welcome");
+    }
+
+    #[tokio::test]
+    async fn record_matched_route() {
+        use crate::route::MatchedRoute;
+
+        let router =
+            axum::Router::new().route("/users/:id", axum::routing::get(|| async { "user" }));
+        let mut service = super::service(router).await;
+
+        let request = http::Request::get("https://www.rust-lang.org/users/42")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+
+        let response = service.call(request).await.unwrap();
+
+        assert_eq!(
+            response.extensions().get::<MatchedRoute>(),
+            Some(&MatchedRoute("/users/:id".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn flag_cold_start_once_per_process() {
+        use crate::init::{ColdStart, InitDuration};
+        use axum::extract::Extension;
+        use std::time::Duration;
+
+        let mut service = super::service_with(|| async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+
+            axum::Router::new().route(
+                "/",
+                axum::routing::get(
+                    |Extension(cold): Extension<ColdStart>,
+                     Extension(init): Extension<InitDuration>| async move {
+                        assert!(init.0 >= Duration::from_millis(10));
+                        cold.0.to_string()
+                    },
+                ),
+            )
+        })
+        .await;
+
+        // Other tests of this process may have handled the first request already, and
+        // an outer layer, like the metrics one, may have flagged the request before
+        for (flagged, expected) in [
+            (None, None),
+            (None, Some("false")),
+            (Some(true), Some("true")),
+        ] {
+            let mut request = http::Request::get("https://www.rust-lang.org/")
+                .body(lambda_http::Body::Empty)
+                .unwrap();
+            if let Some(cold) = flagged {
+                request.extensions_mut().insert(ColdStart(cold));
+            }
+
+            let response = service.call(request).await.unwrap();
+
+            if let Some(expected) = expected {
+                assert_eq!(
+                    response.into_body(),
+                    lambda_http::Body::Binary(expected.as_bytes().to_vec())
+                );
+            }
+        }
+    }
+
     #[tokio::test]
     async fn transform_empty_response() {
-        transform_response_test(|| async { () }, lambda_http::Body::Empty).await;
+        transform_response_test(|| async {}, lambda_http::Body::Empty).await;
     }
 
     #[tokio::test]
@@ -0,0 +1,311 @@
+//! Serve a compiled `bootstrap` locally, as if it was deployed behind an AWS event source.
+//!
+//! ```text
+//! $ cargo install aws-lambda-http-interop --features cli
+//! $ lambda-http-interop-serve --port 3000 --source apigw-v2 ./target/debug/bootstrap
+//! ```
+//!
+//! With `--watch`, the bootstrap is rebuilt and restarted whenever a file changes in the
+//! watched directories. The HTTP server stays up in the meantime, and requests received
+//! during the restart are answered by the new bootstrap.
+//!
+//! With `--record`, every event and response is saved to a directory, to be replayed
+//! in tests with `testing::replay`.
+
+use aws_lambda_http_interop::emulator::RuntimeEmulator;
+use aws_lambda_http_interop::proxy::{self, EventSource, Proxy};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::process::{Child, Command};
+
+const USAGE: &str = "Usage: lambda-http-interop-serve [--port <port>] [--source <alb|apigw-v1|apigw-v2|url>] [--watch <dir>]... [--build <command>] [--record <dir>] <bootstrap> [args...]";
+
+/// How often watched directories are scanned for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    port: u16,
+    source: EventSource,
+    watch: Vec<PathBuf>,
+    build: Option<String>,
+    record: Option<PathBuf>,
+    bootstrap: PathBuf,
+    bootstrap_args: Vec<String>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut args = args.into_iter();
+    let mut port = 3000;
+    let mut source = EventSource::ApiGatewayV2;
+    let mut watch = vec![];
+    let mut build = None;
+    let mut record = None;
+
+    let bootstrap = loop {
+        match args.next().as_deref() {
+            Some("--port" | "-p") => {
+                let value = args.next().ok_or("--port expects a value")?;
+                port = value
+                    .parse()
+                    .map_err(|_| format!("invalid port {}", value))?;
+            }
+            Some("--source" | "-s") => {
+                source = args.next().ok_or("--source expects a value")?.parse()?;
+            }
+            Some("--watch" | "-w") => {
+                watch.push(PathBuf::from(args.next().ok_or("--watch expects a value")?));
+            }
+            Some("--build" | "-b") => {
+                build = Some(args.next().ok_or("--build expects a value")?);
+            }
+            Some("--record" | "-r") => {
+                record = Some(PathBuf::from(
+                    args.next().ok_or("--record expects a value")?,
+                ));
+            }
+            Some(flag) if flag.starts_with('-') => return Err(format!("unknown flag {}", flag)),
+            Some(bootstrap) => break PathBuf::from(bootstrap),
+            None => return Err("missing bootstrap executable".to_string()),
+        }
+    };
+
+    // Watching is only useful if the bootstrap gets rebuilt
+    if !watch.is_empty() && build.is_none() {
+        build = Some("cargo build".to_string());
+    }
+
+    Ok(Args {
+        port,
+        source,
+        watch,
+        build,
+        record,
+        bootstrap,
+        bootstrap_args: args.collect(),
+    })
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match serve(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn serve(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let emulator = Arc::new(RuntimeEmulator::start().await?);
+    let mut bootstrap = Some(spawn(&args, &emulator)?);
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], args.port));
+    eprintln!("Serving {} on http://{}", args.bootstrap.display(), addr);
+
+    // The server is kept across restarts of the bootstrap, so the socket stays open
+    let mut proxy = Proxy::new(args.source, emulator.clone());
+    if let Some(dir) = &args.record {
+        proxy = proxy.with_recording(dir);
+    }
+
+    let server = proxy::serve(addr, proxy);
+    tokio::pin!(server);
+
+    let mut last_change = latest_modification(&args.watch);
+    // Polled along with the server, which keeps answering requests during the build
+    let mut building: Option<Pin<Box<dyn Future<Output = std::io::Result<bool>> + '_>>> = None;
+
+    loop {
+        let exited = async {
+            match bootstrap.as_mut() {
+                Some(bootstrap) => bootstrap.wait().await,
+                None => std::future::pending().await,
+            }
+        };
+        let built = async {
+            match building.as_mut() {
+                Some(build) => build.await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            result = &mut server => return Ok(result?),
+            status = exited => {
+                let status = status?;
+                let reason = match emulator.init_error() {
+                    Some(error) => format!("bootstrap failed to initialize: {}", error),
+                    None => format!("bootstrap exited with {}", status),
+                };
+
+                if args.watch.is_empty() {
+                    return Err(reason.into());
+                }
+
+                eprintln!("{}, waiting for changes", reason);
+                bootstrap = None;
+            }
+            change = wait_for_change(&args.watch, last_change), if !args.watch.is_empty() => {
+                last_change = change;
+
+                // A build still running is outdated, dropping it kills the command
+                building = Some(Box::pin(build(&args)));
+            }
+            success = built => {
+                building = None;
+
+                if !success? {
+                    eprintln!("Build failed, waiting for changes");
+                    continue;
+                }
+
+                if let Some(mut previous) = bootstrap.take() {
+                    previous.kill().await?;
+                }
+
+                // Whatever the previous bootstrap was processing is sent to the new one
+                emulator.requeue_in_flight();
+                bootstrap = Some(spawn(&args, &emulator)?);
+                eprintln!("Restarted {}", args.bootstrap.display());
+            }
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+fn spawn(args: &Args, emulator: &RuntimeEmulator) -> Result<Child, String> {
+    Command::new(&args.bootstrap)
+        .args(&args.bootstrap_args)
+        .envs(emulator.env())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("could not start {}: {}", args.bootstrap.display(), e))
+}
+
+/// Run the build command, returning whether it succeeded.
+async fn build(args: &Args) -> Result<bool, std::io::Error> {
+    let command = match &args.build {
+        Some(command) => command,
+        None => return Ok(true),
+    };
+
+    eprintln!("Running {}", command);
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .kill_on_drop(true)
+        .status()
+        .await?;
+
+    Ok(status.success())
+}
+
+/// Poll the watched paths until a file is modified after `since`.
+async fn wait_for_change(paths: &[PathBuf], since: Option<SystemTime>) -> Option<SystemTime> {
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await;
+
+        let latest = latest_modification(paths);
+        if latest > since {
+            return latest;
+        }
+    }
+}
+
+fn latest_modification(paths: &[PathBuf]) -> Option<SystemTime> {
+    paths.iter().filter_map(|path| modified(path)).max()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    let metadata = std::fs::metadata(path).ok()?;
+
+    if !metadata.is_dir() {
+        return metadata.modified().ok();
+    }
+
+    std::fs::read_dir(path)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        // Build outputs change on every build, and would trigger another one
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name != "target" && !name.starts_with('.')
+        })
+        .filter_map(|entry| modified(&entry.path()))
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_args, Args};
+    use aws_lambda_http_interop::proxy::EventSource;
+    use std::path::PathBuf;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(
+            parse(&["./bootstrap"]),
+            Ok(Args {
+                port: 3000,
+                source: EventSource::ApiGatewayV2,
+                watch: vec![],
+                build: None,
+                record: None,
+                bootstrap: PathBuf::from("./bootstrap"),
+                bootstrap_args: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn flags_before_bootstrap() {
+        assert_eq!(
+            parse(&["--port", "8080", "-s", "alb", "./bootstrap", "--port", "1"]),
+            Ok(Args {
+                port: 8080,
+                source: EventSource::Alb,
+                watch: vec![],
+                build: None,
+                record: None,
+                bootstrap: PathBuf::from("./bootstrap"),
+                bootstrap_args: vec!["--port".to_string(), "1".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn watch_builds_with_cargo_by_default() {
+        let args = parse(&["--watch", "src", "-w", "Cargo.toml", "./bootstrap"]).unwrap();
+
+        assert_eq!(
+            args.watch,
+            vec![PathBuf::from("src"), PathBuf::from("Cargo.toml")]
+        );
+        assert_eq!(args.build.as_deref(), Some("cargo build"));
+    }
+
+    #[test]
+    fn invalid_source() {
+        assert!(parse(&["--source", "sqs", "./bootstrap"]).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Connection level information, recovered from the lambda event.
+//!
+//! Lambda invocations do not come from a socket, so what a framework would usually
+//! learn from its server (host, scheme, client address) has to be derived from the
+//! headers and the request context of the event instead.
+
+#[cfg(feature = "actix")]
+use http::header::HeaderValue;
+use http::header::{HeaderMap, HOST};
+use lambda_http::request::RequestContext;
+use std::net::{IpAddr, SocketAddr};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ConnectionInfo {
+    pub host: Option<String>,
+    pub scheme: String,
+    pub peer_ip: Option<IpAddr>,
+}
+
+impl ConnectionInfo {
+    pub(crate) fn from_request(req: &lambda_http::Request) -> ConnectionInfo {
+        let headers = req.headers();
+        let context = req.extensions().get::<RequestContext>();
+
+        let host = header(headers, HOST.as_str())
+            .map(str::to_owned)
+            .or_else(|| context.and_then(domain_name));
+
+        // API Gateway is only reachable over https, ALB tells us what the client used
+        let scheme = header(headers, X_FORWARDED_PROTO)
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_else(|| "https".to_string());
+
+        // The source ip is what API Gateway saw, the forwarded header can be set by the client
+        let peer_ip = context.and_then(source_ip).or_else(|| {
+            header(headers, X_FORWARDED_FOR)
+                .and_then(|hops| hops.split(',').next())
+                .and_then(|ip| ip.trim().parse().ok())
+        });
+
+        ConnectionInfo {
+            host,
+            scheme,
+            peer_ip,
+        }
+    }
+
+    /// Address of the client, as seen by API Gateway or the load balancer.
+    ///
+    /// The client port isn't part of the event, so it is always `0`.
+    pub(crate) fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_ip.map(|ip| SocketAddr::new(ip, 0))
+    }
+
+    /// Add the headers frameworks rely on to compute their own connection information,
+    /// without overriding the ones already present in the event.
+    #[cfg(feature = "actix")]
+    pub(crate) fn complete_headers(&self, headers: &mut HeaderMap) {
+        if let Some(host) = &self.host {
+            if let Ok(value) = HeaderValue::from_str(host) {
+                headers.entry(HOST).or_insert(value);
+            }
+        }
+
+        if let Ok(value) = HeaderValue::from_str(&self.scheme) {
+            headers.entry(X_FORWARDED_PROTO).or_insert(value);
+        }
+
+        if let Some(ip) = self.peer_ip {
+            if let Ok(value) = HeaderValue::from_str(&ip.to_string()) {
+                headers.entry(X_FORWARDED_FOR).or_insert(value);
+            }
+        }
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+}
+
+fn domain_name(context: &RequestContext) -> Option<String> {
+    match context {
+        RequestContext::ApiGatewayV1(ctx) => ctx.domain_name.clone(),
+        RequestContext::ApiGatewayV2(ctx) => ctx.domain_name.clone(),
+        RequestContext::WebSocket(ctx) => ctx.domain_name.clone(),
+        RequestContext::Alb(_) => None,
+    }
+}
+
+fn source_ip(context: &RequestContext) -> Option<IpAddr> {
+    let ip = match context {
+        RequestContext::ApiGatewayV1(ctx) => ctx.identity.source_ip.as_deref(),
+        RequestContext::ApiGatewayV2(ctx) => ctx.http.source_ip.as_deref(),
+        RequestContext::WebSocket(ctx) => ctx.identity.source_ip.as_deref(),
+        RequestContext::Alb(_) => None,
+    };
+
+    ip.and_then(|ip| ip.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConnectionInfo;
+    use lambda_http::request::RequestContext;
+    use serde_json::json;
+
+    fn request(headers: &[(&str, &str)], context: RequestContext) -> lambda_http::Request {
+        let mut builder = http::Request::get("/hey");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+
+        let mut request = builder.body(lambda_http::Body::Empty).unwrap();
+        request.extensions_mut().insert(context);
+        request
+    }
+
+    // Contexts of captured events, see `tests/fixtures`
+
+    fn api_gateway_v1() -> RequestContext {
+        RequestContext::ApiGatewayV1(
+            serde_json::from_value(json!({
+                "accountId": "12345678912",
+                "resourceId": "roq9wj",
+                "stage": "testStage",
+                "domainName": "gy415nuibc.execute-api.us-east-2.amazonaws.com",
+                "domainPrefix": "y0ne18dixk",
+                "requestId": "deef4878-7910-11e6-8f14-25afc3e9ae33",
+                "protocol": "HTTP/1.1",
+                "identity": {
+                    "sourceIp": "192.168.196.186",
+                    "userAgent": "PostmanRuntime/2.4.5"
+                },
+                "resourcePath": "/{proxy+}",
+                "httpMethod": "GET",
+                "requestTime": "15/May/2020:06:01:09 +0000",
+                "requestTimeEpoch": 1589522469693u64,
+                "apiId": "gy415nuibc"
+            }))
+            .unwrap(),
+        )
+    }
+
+    fn api_gateway_v2() -> RequestContext {
+        RequestContext::ApiGatewayV2(
+            serde_json::from_value(json!({
+                "accountId": "123456789012",
+                "apiId": "aaaaaaaaaa",
+                "domainName": "aaaaaaaaaa.execute-api.us-west-2.amazonaws.com",
+                "domainPrefix": "aaaaaaaaaa",
+                "http": {
+                    "method": "GET",
+                    "path": "/hey",
+                    "protocol": "HTTP/1.1",
+                    "sourceIp": "1.2.3.4",
+                    "userAgent": "curl/7.58.0"
+                },
+                "requestId": "LV7fzho-PHcEJPw=",
+                "routeKey": "$default",
+                "stage": "$default",
+                "time": "21/Apr/2020:15:08:21 +0000",
+                "timeEpoch": 1587481701067u64
+            }))
+            .unwrap(),
+        )
+    }
+
+    fn alb() -> RequestContext {
+        RequestContext::Alb(
+            serde_json::from_value(json!({
+                "elb": { "targetGroupArn": "arn:aws:elasticloadbalancing:region:123456789012:targetgroup/my-target-group/6d0ecf831eec9f09" }
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn info_from_api_gateway_v1_context() {
+        let info = ConnectionInfo::from_request(&request(&[], api_gateway_v1()));
+
+        assert_eq!(
+            info,
+            ConnectionInfo {
+                host: Some("gy415nuibc.execute-api.us-east-2.amazonaws.com".to_string()),
+                scheme: "https".to_string(),
+                peer_ip: Some("192.168.196.186".parse().unwrap()),
+            }
+        );
+    }
+
+    #[test]
+    fn info_from_api_gateway_v2_context() {
+        let req = request(&[("host", "example.com")], api_gateway_v2());
+        let info = ConnectionInfo::from_request(&req);
+
+        assert_eq!(info.host.as_deref(), Some("example.com"));
+        assert_eq!(info.peer_ip, Some("1.2.3.4".parse().unwrap()));
+        assert_eq!(info.scheme, "https");
+    }
+
+    #[test]
+    fn info_from_alb_headers() {
+        let req = request(
+            &[
+                ("host", "lambda-846800462-us-east-2.elb.amazonaws.com"),
+                ("x-forwarded-for", "72.21.198.66, 10.0.0.1"),
+                ("x-forwarded-proto", "http"),
+            ],
+            alb(),
+        );
+        let info = ConnectionInfo::from_request(&req);
+
+        assert_eq!(
+            info.host.as_deref(),
+            Some("lambda-846800462-us-east-2.elb.amazonaws.com")
+        );
+        assert_eq!(info.peer_ip, Some("72.21.198.66".parse().unwrap()));
+        assert_eq!(info.peer_addr(), Some("72.21.198.66:0".parse().unwrap()));
+        assert_eq!(info.scheme, "http");
+    }
+
+    #[cfg(feature = "actix")]
+    #[test]
+    fn complete_headers_keeps_existing_values() {
+        let req = request(&[("x-forwarded-proto", "http")], api_gateway_v1());
+        let info = ConnectionInfo::from_request(&req);
+
+        let mut headers = req.headers().clone();
+        info.complete_headers(&mut headers);
+
+        assert_eq!(
+            headers["host"],
+            "gy415nuibc.execute-api.us-east-2.amazonaws.com"
+        );
+        assert_eq!(headers["x-forwarded-proto"], "http");
+        assert_eq!(headers["x-forwarded-for"], "192.168.196.186");
+    }
+}
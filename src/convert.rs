@@ -0,0 +1,211 @@
+//! Conversions between [`lambda_http::Body`] and the body types used by the
+//! different frameworks.
+//!
+//! Every framework ends up exchanging raw bytes with the lambda layer, so the
+//! adapters only have to bridge between those bytes and their own body type.
+//! Going into a framework is synchronous, as the lambda body is already in memory.
+//! Coming back is not, because framework bodies are usually streams which need
+//! to be accumulated: AWS lambda does not support chunked responses.
+
+use bytes::Bytes;
+use lambda_http::Body;
+use std::future::{ready, Future, Ready};
+#[cfg(any(feature = "hyper", feature = "actix"))]
+use std::pin::Pin;
+
+/// A body which can be created from the body of a lambda request.
+pub trait FromLambdaBody {
+    fn from_lambda_body(body: Body) -> Self;
+}
+
+/// A body which can be turned into the body of a lambda response.
+pub trait IntoLambdaBody {
+    type Future: Future<Output = Result<Body, lambda_http::Error>>;
+
+    fn into_lambda_body(self) -> Self::Future;
+}
+
+/// Extract the raw content of a lambda body, regardless of how it was encoded in the event.
+fn lambda_body_to_bytes(body: Body) -> Bytes {
+    match body {
+        Body::Empty => Bytes::new(),
+        Body::Text(s) => Bytes::from(s),
+        Body::Binary(b) => Bytes::from(b),
+    }
+}
+
+/// Build the lambda body corresponding to a fully buffered framework response.
+///
+/// An empty content is mapped to [`Body::Empty`] so that no body gets serialized
+/// in the lambda response.
+fn bytes_to_lambda_body(bytes: Bytes) -> Body {
+    if bytes.is_empty() {
+        Body::Empty
+    } else {
+        // TODO how do we decide between Body::String and Body::Binary ?
+        Body::Binary(bytes.to_vec())
+    }
+}
+
+impl FromLambdaBody for Bytes {
+    fn from_lambda_body(body: Body) -> Self {
+        lambda_body_to_bytes(body)
+    }
+}
+
+impl IntoLambdaBody for Bytes {
+    type Future = Ready<Result<Body, lambda_http::Error>>;
+
+    fn into_lambda_body(self) -> Self::Future {
+        ready(Ok(bytes_to_lambda_body(self)))
+    }
+}
+
+#[cfg(feature = "hyper")]
+impl FromLambdaBody for hyper::Body {
+    fn from_lambda_body(body: Body) -> Self {
+        hyper::Body::from(lambda_body_to_bytes(body))
+    }
+}
+
+#[cfg(feature = "hyper")]
+impl IntoLambdaBody for hyper::Body {
+    type Future = Pin<Box<dyn Future<Output = Result<Body, lambda_http::Error>> + Send>>;
+
+    fn into_lambda_body(self) -> Self::Future {
+        Box::pin(async move {
+            let bytes = hyper::body::to_bytes(self).await?;
+
+            Ok(bytes_to_lambda_body(bytes))
+        })
+    }
+}
+
+#[cfg(feature = "axum")]
+impl IntoLambdaBody for axum::body::BoxBody {
+    type Future = Pin<Box<dyn Future<Output = Result<Body, lambda_http::Error>> + Send>>;
+
+    fn into_lambda_body(self) -> Self::Future {
+        Box::pin(async move {
+            let bytes = hyper::body::to_bytes(self).await?;
+
+            Ok(bytes_to_lambda_body(bytes))
+        })
+    }
+}
+
+#[cfg(feature = "actix")]
+impl FromLambdaBody for actix_http::Payload {
+    fn from_lambda_body(body: Body) -> Self {
+        let (_, mut payload) = actix_http::h1::Payload::create(true);
+
+        payload.unread_data(lambda_body_to_bytes(body));
+
+        payload.into()
+    }
+}
+
+/// Wrapper around an actix [`MessageBody`](actix_web::body::MessageBody).
+///
+/// [`Bytes`] is itself a `MessageBody`, so a blanket implementation of
+/// [`IntoLambdaBody`] isn't possible.
+#[cfg(feature = "actix")]
+pub struct ActixBody<B>(pub B);
+
+#[cfg(feature = "actix")]
+impl<B> IntoLambdaBody for ActixBody<B>
+where
+    B: actix_web::body::MessageBody + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = Result<Body, lambda_http::Error>>>>;
+
+    fn into_lambda_body(self) -> Self::Future {
+        Box::pin(async move {
+            // MessageBody errors are not required to be Send, so we can only keep their message
+            let bytes = actix_web::body::to_bytes(self.0)
+                .await
+                .map_err(|e| lambda_http::Error::from(e.into().to_string()))?;
+
+            Ok(bytes_to_lambda_body(bytes))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FromLambdaBody, IntoLambdaBody};
+    use bytes::Bytes;
+    use lambda_http::Body;
+
+    #[test]
+    fn bytes_from_lambda_body() {
+        assert_eq!(Bytes::from_lambda_body(Body::Empty), Bytes::new());
+        assert_eq!(
+            Bytes::from_lambda_body(Body::Text("hello".to_string())),
+            Bytes::from("hello")
+        );
+        assert_eq!(
+            Bytes::from_lambda_body(Body::Binary(vec![0, 1, 2])),
+            Bytes::from(vec![0, 1, 2])
+        );
+    }
+
+    #[tokio::test]
+    async fn bytes_into_lambda_body() {
+        assert_eq!(Bytes::new().into_lambda_body().await.unwrap(), Body::Empty);
+        assert_eq!(
+            Bytes::from("hello").into_lambda_body().await.unwrap(),
+            Body::Binary("hello".as_bytes().to_vec())
+        );
+    }
+
+    #[cfg(feature = "hyper")]
+    #[tokio::test]
+    async fn hyper_body_round_trip() {
+        let body = hyper::Body::from_lambda_body(Body::Text("hello".to_string()));
+
+        assert_eq!(
+            body.into_lambda_body().await.unwrap(),
+            Body::Binary("hello".as_bytes().to_vec())
+        );
+    }
+
+    #[cfg(feature = "hyper")]
+    #[tokio::test]
+    async fn hyper_streamed_body_into_lambda_body() {
+        let chunks: Vec<Result<_, std::io::Error>> = vec![Ok("Hello"), Ok(" "), Ok("World!")];
+        let body = hyper::Body::wrap_stream(futures::stream::iter(chunks));
+
+        assert_eq!(
+            body.into_lambda_body().await.unwrap(),
+            Body::Binary("Hello World!".as_bytes().to_vec())
+        );
+    }
+
+    #[cfg(feature = "actix")]
+    #[tokio::test]
+    async fn actix_payload_from_lambda_body() {
+        use futures::StreamExt;
+
+        let mut payload = actix_http::Payload::from_lambda_body(Body::Binary(vec![0, 1, 2]));
+
+        let chunk = payload.next().await.unwrap().unwrap();
+        assert_eq!(chunk, Bytes::from(vec![0, 1, 2]));
+    }
+
+    #[cfg(feature = "actix")]
+    #[tokio::test]
+    async fn actix_body_into_lambda_body() {
+        use super::ActixBody;
+        use actix_web::body::BoxBody;
+
+        assert_eq!(ActixBody(()).into_lambda_body().await.unwrap(), Body::Empty);
+        assert_eq!(
+            ActixBody(BoxBody::new("hello"))
+                .into_lambda_body()
+                .await
+                .unwrap(),
+            Body::Binary("hello".as_bytes().to_vec())
+        );
+    }
+}
@@ -0,0 +1,361 @@
+//! In-process emulator of the [Lambda Runtime API].
+//!
+//! This lets `lambda_runtime::run` (and everything built on top of it, like
+//! `actix::run`) be exercised end-to-end without AWS or docker: events queued with
+//! [`RuntimeEmulator::invoke`] are handed to the runtime when it asks for its next
+//! invocation, and what it reports back is returned to the caller.
+//!
+//! [Lambda Runtime API]: https://docs.aws.amazon.com/lambda/latest/dg/runtimes-api.html
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{oneshot, Notify};
+
+const PREFIX: &str = "/2018-06-01/runtime";
+
+/// Emulated function ARN, given to the runtime with every invocation.
+pub const FUNCTION_ARN: &str = "arn:aws:lambda:us-east-1:123456789012:function:emulated";
+
+/// How long the function has to process an invocation.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What went wrong with an invocation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvocationError {
+    /// The function reported an error, the payload is the JSON it sent.
+    Function(String),
+    /// The emulator stopped before the function answered.
+    Dropped,
+}
+
+impl std::fmt::Display for InvocationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvocationError::Function(payload) => {
+                write!(f, "function returned an error: {}", payload)
+            }
+            InvocationError::Dropped => {
+                write!(f, "the emulator stopped before the function answered")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvocationError {}
+
+type InvocationResult = Result<Vec<u8>, InvocationError>;
+
+struct Invocation {
+    id: String,
+    event: Vec<u8>,
+    respond_to: oneshot::Sender<InvocationResult>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: AtomicU64,
+    queue: Mutex<VecDeque<Invocation>>,
+    queued: Notify,
+    in_flight: Mutex<HashMap<String, Invocation>>,
+    init_error: Mutex<Option<String>>,
+}
+
+/// A running Runtime API emulator, stopped when dropped.
+pub struct RuntimeEmulator {
+    addr: SocketAddr,
+    state: Arc<State>,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl RuntimeEmulator {
+    /// Start the emulator on a random local port.
+    pub async fn start() -> Result<RuntimeEmulator, hyper::Error> {
+        let state = Arc::new(State::default());
+
+        let make = {
+            let state = state.clone();
+            make_service_fn(move |_| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+            })
+        };
+
+        let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))?.serve(make);
+        let addr = server.local_addr();
+
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            let _ = stopped.await;
+        }));
+
+        Ok(RuntimeEmulator {
+            addr,
+            state,
+            _shutdown: shutdown,
+        })
+    }
+
+    /// Address of the emulator, the value of `AWS_LAMBDA_RUNTIME_API`.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Environment a lambda runtime needs to connect to this emulator.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("AWS_LAMBDA_RUNTIME_API", self.addr.to_string()),
+            ("AWS_LAMBDA_FUNCTION_NAME", "emulated".to_string()),
+            ("AWS_LAMBDA_FUNCTION_MEMORY_SIZE", "128".to_string()),
+            ("AWS_LAMBDA_FUNCTION_VERSION", "$LATEST".to_string()),
+            (
+                "AWS_LAMBDA_LOG_GROUP_NAME",
+                "/aws/lambda/emulated".to_string(),
+            ),
+            ("AWS_LAMBDA_LOG_STREAM_NAME", "emulated".to_string()),
+        ]
+    }
+
+    /// Point the lambda runtime of the current process to this emulator.
+    ///
+    /// Environment variables are process wide, so only one emulator can be used
+    /// that way at a time. Spawned processes should use [`env`](Self::env) instead.
+    pub fn set_env(&self) {
+        for (name, value) in self.env() {
+            std::env::set_var(name, value);
+        }
+    }
+
+    /// Queue an event and wait for the function to process it, returning its response.
+    pub async fn invoke(&self, event: impl Into<Vec<u8>>) -> Result<Vec<u8>, InvocationError> {
+        let id = format!(
+            "00000000-0000-0000-0000-{:012}",
+            self.state.next_id.fetch_add(1, Ordering::SeqCst)
+        );
+        let (respond_to, response) = oneshot::channel();
+
+        self.state.queue.lock().unwrap().push_back(Invocation {
+            id,
+            event: event.into(),
+            respond_to,
+        });
+        self.state.queued.notify_one();
+
+        response.await.unwrap_or(Err(InvocationError::Dropped))
+    }
+
+    /// Hand the invocations the runtime did not answer yet to the next runtime
+    /// asking for one, typically after restarting the function.
+    pub fn requeue_in_flight(&self) {
+        let in_flight: Vec<Invocation> = self
+            .state
+            .in_flight
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, invocation)| invocation)
+            .collect();
+
+        let mut queue = self.state.queue.lock().unwrap();
+        for invocation in in_flight {
+            queue.push_front(invocation);
+            self.state.queued.notify_one();
+        }
+    }
+
+    /// Error reported by the runtime if it failed to initialize.
+    pub fn init_error(&self) -> Option<String> {
+        self.state.init_error.lock().unwrap().clone()
+    }
+}
+
+async fn handle(state: Arc<State>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let path = req.uri().path().to_string();
+    let route = path.strip_prefix(PREFIX).unwrap_or_default();
+    let segments: Vec<&str> = route.split('/').filter(|s| !s.is_empty()).collect();
+
+    let response = match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["invocation", "next"]) => next_invocation(&state).await,
+        (&Method::POST, ["invocation", id, "response"]) => {
+            let body = hyper::body::to_bytes(req.into_body())
+                .await
+                .unwrap_or_default();
+
+            complete(&state, id, Ok(body.to_vec()))
+        }
+        (&Method::POST, ["invocation", id, "error"]) => {
+            let body = hyper::body::to_bytes(req.into_body())
+                .await
+                .unwrap_or_default();
+            let payload = String::from_utf8_lossy(&body).into_owned();
+
+            complete(&state, id, Err(InvocationError::Function(payload)))
+        }
+        (&Method::POST, ["init", "error"]) => {
+            let body = hyper::body::to_bytes(req.into_body())
+                .await
+                .unwrap_or_default();
+            *state.init_error.lock().unwrap() = Some(String::from_utf8_lossy(&body).into_owned());
+
+            status(StatusCode::ACCEPTED)
+        }
+        _ => status(StatusCode::NOT_FOUND),
+    };
+
+    Ok(response)
+}
+
+/// Long poll until an event is queued, like the real Runtime API does.
+async fn next_invocation(state: &State) -> Response<Body> {
+    let invocation = loop {
+        if let Some(invocation) = state.queue.lock().unwrap().pop_front() {
+            break invocation;
+        }
+
+        state.queued.notified().await;
+    };
+
+    let deadline = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        + TIMEOUT;
+
+    let response = Response::builder()
+        .header("lambda-runtime-aws-request-id", &invocation.id)
+        .header(
+            "lambda-runtime-deadline-ms",
+            deadline.as_millis().to_string(),
+        )
+        .header("lambda-runtime-invoked-function-arn", FUNCTION_ARN)
+        .header(
+            "lambda-runtime-trace-id",
+            "Root=1-5bdb40ca-556d8b0c50dc66f0511bf520;Parent=53995c3f42cd8ad8;Sampled=1",
+        )
+        .header("content-type", "application/json")
+        .body(Body::from(invocation.event.clone()))
+        .expect("next invocation response should be valid");
+
+    state
+        .in_flight
+        .lock()
+        .unwrap()
+        .insert(invocation.id.clone(), invocation);
+
+    response
+}
+
+fn complete(state: &State, id: &str, result: InvocationResult) -> Response<Body> {
+    match state.in_flight.lock().unwrap().remove(id) {
+        Some(invocation) => {
+            // The caller may have stopped waiting, which is fine
+            let _ = invocation.respond_to.send(result);
+
+            status(StatusCode::ACCEPTED)
+        }
+        None => status(StatusCode::NOT_FOUND),
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("empty response should be valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RuntimeEmulator;
+    #[cfg(feature = "runtime")]
+    use {
+        super::InvocationError,
+        serde_json::{json, Value},
+    };
+
+    #[tokio::test]
+    async fn requeue_unanswered_invocation() {
+        let emulator = RuntimeEmulator::start().await.unwrap();
+        let next = format!(
+            "http://{}/2018-06-01/runtime/invocation/next",
+            emulator.addr()
+        );
+
+        let invocation = emulator.invoke("{}");
+        let runtime = async {
+            let first = reqwest::get(&next).await.unwrap();
+            let first_id = first.headers()["lambda-runtime-aws-request-id"].clone();
+
+            // The runtime crashed, the next one gets the same invocation
+            emulator.requeue_in_flight();
+
+            let second = reqwest::get(&next).await.unwrap();
+            let second_id = second.headers()["lambda-runtime-aws-request-id"].clone();
+            assert_eq!(first_id, second_id);
+
+            reqwest::Client::new()
+                .post(format!(
+                    "http://{}/2018-06-01/runtime/invocation/{}/response",
+                    emulator.addr(),
+                    second_id.to_str().unwrap()
+                ))
+                .body("ok")
+                .send()
+                .await
+                .unwrap();
+        };
+
+        let (response, ()) = tokio::join!(invocation, runtime);
+
+        assert_eq!(response.unwrap(), b"ok");
+    }
+
+    #[cfg(feature = "runtime")]
+    async fn func(
+        event: lambda_runtime::LambdaEvent<Value>,
+    ) -> Result<Value, lambda_runtime::Error> {
+        let (event, context) = event.into_parts();
+
+        match event["firstName"].as_str() {
+            Some(first_name) => Ok(json!({
+                "message": format!("Hello, {}!", first_name),
+                "arn": context.invoked_function_arn,
+            })),
+            None => Err("firstName is missing".into()),
+        }
+    }
+
+    // Environment variables are process wide, so everything is tested with the same emulator
+    #[cfg(feature = "runtime")]
+    #[tokio::test]
+    async fn run_lambda_tower_against_emulator() {
+        let emulator = RuntimeEmulator::start().await.unwrap();
+        emulator.set_env();
+
+        tokio::spawn(crate::runtime::run_lambda_tower(tower::service_fn(func)));
+
+        let response = emulator
+            .invoke(json!({ "firstName": "Jane" }).to_string())
+            .await
+            .unwrap();
+        let response: Value = serde_json::from_slice(&response).unwrap();
+
+        assert_eq!(
+            response,
+            json!({ "message": "Hello, Jane!", "arn": super::FUNCTION_ARN })
+        );
+
+        let error = emulator.invoke("{}").await.unwrap_err();
+
+        match error {
+            InvocationError::Function(payload) => assert!(payload.contains("firstName is missing")),
+            other => panic!("unexpected error {:?}", other),
+        }
+
+        assert_eq!(emulator.init_error(), None);
+    }
+}
@@ -0,0 +1,393 @@
+//! Rendering of the failures of the adapters themselves.
+//!
+//! Applications render their own errors: an actix `ResponseError`, an axum
+//! `IntoResponse`. Some failures happen outside of the application though, like a body
+//! which cannot be converted, a response too large for lambda, or an invocation about
+//! to reach its deadline. By default they fail the invocation, which API Gateway turns
+//! into an opaque `502`. Wrapping a service in [`RenderErrors`] turns them into proper
+//! HTTP responses instead, rendered by an [`ErrorRenderer`].
+
+use http::header::{HeaderValue, CONTENT_TYPE};
+use http::StatusCode;
+use lambda_http::{Body, IntoResponse, Request, Response};
+use serde_json::json;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Largest response payload accepted by lambda for synchronous invocations.
+pub const MAX_RESPONSE_SIZE: usize = 6 * 1024 * 1024;
+
+/// Time left to render and send the response when the deadline is near.
+const DEADLINE_MARGIN: Duration = Duration::from_millis(250);
+
+/// Header giving the lambda request id to clients, to correlate their requests with logs.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// What went wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The service failed, typically while converting the request or the response.
+    Internal,
+    /// The response is larger than what lambda accepts.
+    PayloadTooLarge,
+    /// The invocation was about to reach its deadline.
+    Timeout,
+    /// The framework failed the request instead of responding, like an actix
+    /// middleware returning an error. The status is the one the framework would have
+    /// responded with.
+    Framework(StatusCode),
+}
+
+impl ErrorKind {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorKind::PayloadTooLarge => StatusCode::BAD_GATEWAY,
+            ErrorKind::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorKind::Framework(status) => *status,
+        }
+    }
+}
+
+/// A failure to render, with the invocation it happened in.
+#[derive(Debug)]
+pub struct Failure<'a> {
+    pub kind: ErrorKind,
+    pub error: &'a (dyn std::error::Error + Send + Sync),
+    /// Request id of the lambda invocation, if known.
+    pub request_id: Option<&'a str>,
+}
+
+/// Turns failures into responses.
+pub trait ErrorRenderer: Send + Sync + 'static {
+    fn render(&self, failure: &Failure<'_>) -> Response<Body>;
+}
+
+/// Renders failures as [RFC 7807] `application/problem+json` documents, with the
+/// request id in both the document and the `x-request-id` header.
+///
+/// Error messages can reveal implementation details, so they are only included in
+/// the `detail` member if `expose_details` is set.
+///
+/// [RFC 7807]: https://www.rfc-editor.org/rfc/rfc7807
+#[derive(Debug, Clone, Default)]
+pub struct ProblemJson {
+    pub expose_details: bool,
+}
+
+impl ErrorRenderer for ProblemJson {
+    fn render(&self, failure: &Failure<'_>) -> Response<Body> {
+        let status = failure.kind.status();
+
+        let mut problem = json!({
+            "type": "about:blank",
+            "title": status.canonical_reason().unwrap_or_default(),
+            "status": status.as_u16(),
+        });
+        if self.expose_details {
+            problem["detail"] = json!(failure.error.to_string());
+        }
+        if let Some(request_id) = failure.request_id {
+            problem["requestId"] = json!(request_id);
+        }
+
+        let mut response = Response::new(Body::Text(problem.to_string()));
+        *response.status_mut() = status;
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+
+        if let Some(value) = failure
+            .request_id
+            .and_then(|id| HeaderValue::from_str(id).ok())
+        {
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+
+        response
+    }
+}
+
+/// Renderer of the enclosing [`RenderErrors`], handed to the adapters in the request
+/// extensions so that the errors of the frameworks are rendered by it as well.
+#[derive(Clone)]
+pub(crate) struct Renderer(pub(crate) Arc<dyn ErrorRenderer>);
+
+impl Renderer {
+    /// Render an error of the framework, which would otherwise have been turned into a
+    /// response with `status`.
+    #[cfg_attr(not(feature = "actix"), allow(dead_code))]
+    pub(crate) fn render_framework_error(
+        &self,
+        status: StatusCode,
+        error: &(dyn std::error::Error + Send + Sync),
+        request_id: Option<&str>,
+    ) -> Response<Body> {
+        let kind = ErrorKind::Framework(status);
+        log_error!(
+            "RequestId: {} {:?}: {}",
+            request_id.unwrap_or("-"),
+            kind,
+            error
+        );
+
+        self.0.render(&Failure {
+            kind,
+            error,
+            request_id,
+        })
+    }
+}
+
+/// Wrap a service so that its failures are rendered by `renderer`.
+///
+/// The actix adapter also renders the errors returned by the application, typically by
+/// a middleware, with `renderer` instead of their `ResponseError` implementation.
+///
+/// ```ignore
+/// let service = actix::service(factory).await?;
+/// lambda_http::run(error::render_errors(service, ProblemJson::default())).await?;
+/// ```
+pub fn render_errors<S, E>(service: S, renderer: E) -> RenderErrors<S, E> {
+    RenderErrors {
+        service,
+        renderer: Arc::new(renderer),
+    }
+}
+
+/// Service created by [`render_errors`].
+pub struct RenderErrors<S, E> {
+    service: S,
+    renderer: Arc<E>,
+}
+
+impl<S, R, E> tower::Service<Request> for RenderErrors<S, E>
+where
+    S: tower::Service<Request, Response = R, Error = lambda_http::Error>,
+    S::Future: Send + 'static,
+    R: IntoResponse,
+    E: ErrorRenderer,
+{
+    type Response = Response<Body>;
+    type Error = lambda_http::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, lambda_http::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Not tied to a request, so there is nothing to render
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let renderer: Arc<dyn ErrorRenderer> = self.renderer.clone();
+        req.extensions_mut().insert(Renderer(renderer));
+
+        let context = req.extensions().get::<lambda_runtime::Context>();
+        let request_id = context
+            .map(|context| context.request_id.clone())
+            .filter(|id| !id.is_empty());
+        let time_left = context.and_then(|context| time_left(context.deadline));
+
+        let renderer = self.renderer.clone();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let result = match time_left {
+                Some(time_left) => match tokio::time::timeout(time_left, fut).await {
+                    Ok(result) => result.map_err(|e| (ErrorKind::Internal, e)),
+                    Err(elapsed) => Err((ErrorKind::Timeout, elapsed.into())),
+                },
+                None => fut.await.map_err(|e| (ErrorKind::Internal, e)),
+            };
+
+            let (kind, error) = match result.map(IntoResponse::into_response) {
+                Ok(response) if body_size(response.body()) <= MAX_RESPONSE_SIZE => {
+                    return Ok(response)
+                }
+                Ok(response) => (
+                    ErrorKind::PayloadTooLarge,
+                    format!(
+                        "response body of {} bytes exceeds the lambda limit of {} bytes",
+                        body_size(response.body()),
+                        MAX_RESPONSE_SIZE
+                    )
+                    .into(),
+                ),
+                Err(failure) => failure,
+            };
+
+            log_error!(
+                "RequestId: {} {:?}: {}",
+                request_id.as_deref().unwrap_or("-"),
+                kind,
+                error
+            );
+
+            Ok(renderer.render(&Failure {
+                kind,
+                error: error.as_ref(),
+                request_id: request_id.as_deref(),
+            }))
+        })
+    }
+}
+
+/// Time until the deadline, minus the margin needed to send a response.
+///
+/// There is no deadline outside of lambda, where the context is a default one. Once
+/// the deadline has passed, there is no time left at all.
+fn time_left(deadline_ms: u64) -> Option<Duration> {
+    if deadline_ms == 0 {
+        return None;
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+
+    Some(
+        Duration::from_millis(deadline_ms)
+            .saturating_sub(now)
+            .saturating_sub(DEADLINE_MARGIN),
+    )
+}
+
+/// Size of the body once in the response payload, where binary bodies are base64 encoded.
+fn body_size(body: &Body) -> usize {
+    match body {
+        Body::Empty => 0,
+        Body::Text(text) => text.len(),
+        Body::Binary(bytes) => bytes.len().div_ceil(3) * 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_errors, ErrorKind, ProblemJson};
+    use lambda_http::{Body, Request, Response};
+    use serde_json::{json, Value};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use tower::Service;
+
+    fn request(deadline: Duration) -> Request {
+        let mut context = lambda_runtime::Context::default();
+        context.request_id = "8476a536-e9f4-11e8-9739-2dfe598c3fcd".to_string();
+        context.deadline = deadline.as_millis() as u64;
+
+        let mut request = http::Request::get("/").body(Body::Empty).unwrap();
+        request.extensions_mut().insert(context);
+
+        request
+    }
+
+    fn in_a_minute() -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap() + Duration::from_secs(60)
+    }
+
+    fn problem(response: Response<Body>) -> Value {
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/problem+json"
+        );
+        assert_eq!(
+            response.headers()["x-request-id"],
+            "8476a536-e9f4-11e8-9739-2dfe598c3fcd"
+        );
+
+        match response.into_body() {
+            Body::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("unexpected body {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn render_service_error() {
+        let failing = tower::service_fn(|_: Request| async {
+            Err::<Response<Body>, _>(lambda_http::Error::from("cannot convert body"))
+        });
+        let renderer = ProblemJson {
+            expose_details: true,
+        };
+
+        let response = render_errors(failing, renderer)
+            .call(request(in_a_minute()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), ErrorKind::Internal.status());
+        assert_eq!(
+            problem(response),
+            json!({
+                "type": "about:blank",
+                "title": "Internal Server Error",
+                "status": 500,
+                "detail": "cannot convert body",
+                "requestId": "8476a536-e9f4-11e8-9739-2dfe598c3fcd"
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn render_oversized_response() {
+        let large = tower::service_fn(|_: Request| async {
+            Ok::<_, lambda_http::Error>(Response::new(Body::Binary(vec![0; 5 * 1024 * 1024])))
+        });
+
+        let response = render_errors(large, ProblemJson::default())
+            .call(request(in_a_minute()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 502);
+        assert_eq!(problem(response)["title"], "Bad Gateway");
+    }
+
+    #[tokio::test]
+    async fn render_timeout_before_deadline() {
+        let slow = tower::service_fn(|_: Request| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok::<_, lambda_http::Error>(Response::new(Body::Empty))
+        });
+
+        let deadline = in_a_minute() - Duration::from_secs(59);
+        let response = render_errors(slow, ProblemJson::default())
+            .call(request(deadline))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 504);
+    }
+
+    #[tokio::test]
+    async fn render_timeout_after_deadline() {
+        let slow = tower::service_fn(|_: Request| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok::<_, lambda_http::Error>(Response::new(Body::Empty))
+        });
+
+        let deadline = in_a_minute() - Duration::from_secs(61);
+        let response = render_errors(slow, ProblemJson::default())
+            .call(request(deadline))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 504);
+    }
+
+    #[tokio::test]
+    async fn pass_responses_through() {
+        let ok = tower::service_fn(|_: Request| async {
+            Ok::<_, lambda_http::Error>("hello".to_string())
+        });
+
+        // Default context of local invocations has no deadline
+        let response = render_errors(ok, ProblemJson::default())
+            .call(request(Duration::ZERO))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.into_body(), Body::Text("hello".to_string()));
+    }
+}
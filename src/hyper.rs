@@ -1,12 +1,19 @@
+use crate::convert::{FromLambdaBody, IntoLambdaBody};
 use hyper::server::conn::AddrIncoming;
 use hyper::{Request, Response, Server};
 use std::future::Future;
 use std::marker::PhantomData;
 use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context as TaskContext, Poll};
+use tokio::sync::Mutex;
 use tower::Service;
 
+/// Serve the service created by `factory` on 127.0.0.1:3000, calling it in-process.
+///
+/// To run an actual bootstrap binary instead, through the same JSON events as a
+/// deployed function, see `proxy::serve` (with the `proxy` feature).
 // Should this return a Server instead ? Will I be able to make it work with the generics ?
 pub async fn serve<'a, F, Fut, R, S, MkErr>(
     factory: F,
@@ -86,7 +93,7 @@ where
         match self.0.as_mut().poll(cx) {
             Poll::Pending => Poll::Pending,
             Poll::Ready(Ok(service)) => Poll::Ready(Ok(LambdaService {
-                service,
+                service: Arc::new(Mutex::new(service)),
                 _phantom_a: PhantomData,
             })),
             Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
@@ -101,13 +108,15 @@ where
     S::Future: Send + 'a,
     R: lambda_http::IntoResponse,
 {
-    service: S,
+    // Requests are only converted once their body has been received, by which time the
+    // service is no longer borrowed by `call`
+    service: Arc<Mutex<S>>,
     _phantom_a: PhantomData<&'a ()>,
 }
 
 impl<'a, S, R> Service<Request<hyper::Body>> for LambdaService<'a, S, R>
 where
-    S: tower::Service<lambda_http::Request, Response = R, Error = lambda_http::Error> + Send,
+    S: tower::Service<lambda_http::Request, Response = R, Error = lambda_http::Error> + Send + 'a,
     S::Future: Send + 'a,
     R: lambda_http::IntoResponse,
 {
@@ -121,8 +130,19 @@ where
     }
 
     fn call(&mut self, req: Request<hyper::Body>) -> Self::Future {
-        let req = hyper_to_lambda_request(req);
-        let fut = Box::pin(self.service.call(req));
+        let service = self.service.clone();
+
+        let fut = Box::pin(async move {
+            let req = hyper_to_lambda_request(req).await?;
+
+            let fut = {
+                let mut service = service.lock().await;
+                std::future::poll_fn(|cx| service.poll_ready(cx)).await?;
+                service.call(req)
+            };
+
+            fut.await
+        });
 
         TransformResponse { fut }
     }
@@ -155,22 +175,58 @@ fn lambda_to_hyper_response(
 ) -> hyper::Response<hyper::Body> {
     let (parts, body) = r.into_parts();
 
-    let body = match body {
-        lambda_http::Body::Empty => hyper::Body::empty(),
-        lambda_http::Body::Text(s) => hyper::Body::from(s),
-        lambda_http::Body::Binary(v) => hyper::Body::from(v),
-    };
-
-    Response::from_parts(parts, body)
+    Response::from_parts(parts, hyper::Body::from_lambda_body(body))
 }
 
-fn hyper_to_lambda_request(r: hyper::Request<hyper::Body>) -> lambda_http::Request {
-    /*
+async fn hyper_to_lambda_request(
+    req: hyper::Request<hyper::Body>,
+) -> Result<lambda_http::Request, lambda_http::Error> {
     let (parts, body) = req.into_parts();
-    let body = hyper::body::to_bytes(body).await?; // TODO needs to bridge that async in the manual world
-    let body = lambda_http::Body::Binary(body.to_vec());
+    let body = body.into_lambda_body().await?;
+
+    let mut req = lambda_http::Request::from_parts(parts, body);
 
-    Request::from_parts(parts, body)
-    */
-    todo!()
+    // lambda_http always gives the invocation context to handlers, an empty one will do
+    req.extensions_mut()
+        .insert(lambda_runtime::Context::default());
+
+    Ok(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LambdaService;
+    use std::marker::PhantomData;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+    use tower::Service;
+
+    async fn echo(
+        req: lambda_http::Request,
+    ) -> Result<lambda_http::Response<lambda_http::Body>, lambda_http::Error> {
+        let response = lambda_http::Response::builder()
+            .header("x-method", req.method().as_str())
+            .body(req.into_body())?;
+
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn call_service_with_hyper_request() {
+        let mut service = LambdaService {
+            service: Arc::new(Mutex::new(tower::service_fn(echo))),
+            _phantom_a: PhantomData,
+        };
+
+        let request = hyper::Request::post("/echo")
+            .body(hyper::Body::from("hello"))
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+
+        assert_eq!(response.headers()["x-method"], "POST");
+        assert_eq!(
+            hyper::body::to_bytes(response.into_body()).await.unwrap(),
+            "hello"
+        );
+    }
 }
@@ -0,0 +1,43 @@
+//! Cold starts, and the time spent creating the application.
+//!
+//! Both are request extensions set by the adapters, available to handlers and
+//! middleware like any other extension (`web::ReqData` with actix, `Extension` with
+//! axum) to log or measure them.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Whether no invocation was handled by this process yet. Lambda starts a process per
+/// execution environment, so its first invocation is the cold start.
+static COLD: AtomicBool = AtomicBool::new(true);
+
+/// Whether the request is the first one handled by the process, right after the
+/// application was created during a cold start of the lambda.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColdStart(pub bool);
+
+impl ColdStart {
+    /// Cold start of the request, as already flagged by an outer layer (like the
+    /// [`MetricsLayer`](crate::metrics::MetricsLayer)), or else flagged now, so that
+    /// every layer and the application agree on it.
+    pub(crate) fn of<B>(req: &mut http::Request<B>) -> ColdStart {
+        if let Some(cold_start) = req.extensions().get::<ColdStart>() {
+            return *cold_start;
+        }
+
+        let cold_start = ColdStart(COLD.swap(false, Ordering::SeqCst));
+        req.extensions_mut().insert(cold_start);
+
+        cold_start
+    }
+}
+
+/// How long the application took to be created, during the init phase of the lambda:
+/// the actix `new_service` call of the factory in
+/// [`actix::service`](crate::actix::service), or the router construction given to
+/// [`axum::service_with`](crate::axum::service_with).
+///
+/// Set on every request, so that regressions of the init duration can be tracked from
+/// the cold start requests, which are the ones paying for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InitDuration(pub Duration);
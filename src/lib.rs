@@ -1,16 +1,65 @@
+/// Report a failure which cannot be returned to the caller, through `tracing` when the
+/// feature is enabled, and on stderr otherwise, which lambda sends to CloudWatch Logs.
+macro_rules! log_error {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::error!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        eprintln!($($arg)+);
+    }};
+}
+
+#[cfg(feature = "actix")]
 pub mod actix;
+#[cfg(feature = "axum")]
 pub mod axum;
+#[cfg(any(feature = "actix", feature = "axum"))]
+mod connection;
+pub mod convert;
+#[cfg(feature = "emulator")]
+pub mod emulator;
+pub mod error;
+#[cfg(feature = "hyper-dev")]
 pub mod hyper;
+#[cfg(any(feature = "actix", feature = "axum", feature = "metrics"))]
+pub mod init;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "opentelemetry")]
+pub mod otel;
+#[cfg(any(feature = "actix", feature = "axum"))]
+pub mod path;
+#[cfg(feature = "poem")]
+pub mod poem;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+#[cfg(feature = "rocket")]
+pub mod rocket;
+pub mod route;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "tracing")]
+pub mod trace;
+#[cfg(any(feature = "actix", feature = "axum"))]
+mod uri;
+#[cfg(any(feature = "actix", feature = "axum", feature = "opentelemetry"))]
+mod xray;
 
 /// Interop for the basic lambda event, without any HTTP layer shim.
 /// I don't think there is much value here to be honest, because I'm
 /// ready to bet that most of the actix ecosystem is around actix-http
 /// and not raw services.
 /// Still a good starting point :)
+#[cfg(feature = "runtime")]
 pub mod runtime {
-    use lambda_runtime::{Error, LambdaEvent};
+    use lambda_runtime::{Context, Error, LambdaEvent};
+    use serde::de::DeserializeOwned;
     use serde::{Deserialize, Serialize};
+    use serde_json::Value;
     use std::future::Future;
+    use std::marker::PhantomData;
+    use std::pin::Pin;
+    use std::task::Poll;
 
     pub async fn run_lambda_tower<A, B, F>(handler: F) -> Result<(), Error>
     where
@@ -23,21 +72,143 @@ pub mod runtime {
         lambda_runtime::run(handler).await
     }
 
-    pub async fn run_lambda_actix<A, B, F>(handler: F) -> Result<(), Error>
+    /// Wrap an `async fn(T, Context) -> Result<U, E>` into a service for
+    /// [`run_lambda_tower`].
+    ///
+    /// The event is deserialized by the service rather than by the runtime, so an
+    /// invalid payload fails the invocation instead of the whole runtime.
+    ///
+    /// ```ignore
+    /// run_lambda_tower(runtime::handler(|order: Order, _ctx| async move {
+    ///     Ok::<_, Error>(Receipt::for_order(order))
+    /// }))
+    /// .await?;
+    /// ```
+    pub fn handler<F, Fut, T, U, E>(f: F) -> TypedHandler<F, T>
     where
-        F: actix_web::dev::Service<LambdaEvent<A>, Response = B>,
-        F::Error: std::fmt::Debug + std::fmt::Display,
-        A: for<'de> Deserialize<'de>,
-        B: Serialize,
+        F: Fn(T, Context) -> Fut,
+        Fut: Future<Output = Result<U, E>> + Send + 'static,
+        T: DeserializeOwned,
+        U: Serialize,
+        E: std::fmt::Display,
     {
-        let s = tower::service_fn(|req| handler.call(req));
+        TypedHandler {
+            f,
+            _phantom: PhantomData,
+        }
+    }
 
-        lambda_runtime::run(s).await
+    /// Service created by [`handler`].
+    pub struct TypedHandler<F, T> {
+        f: F,
+        _phantom: PhantomData<fn(T)>,
+    }
+
+    impl<F, Fut, T, U, E> tower::Service<LambdaEvent<Value>> for TypedHandler<F, T>
+    where
+        F: Fn(T, Context) -> Fut,
+        Fut: Future<Output = Result<U, E>> + Send + 'static,
+        T: DeserializeOwned,
+        U: Serialize,
+        E: std::fmt::Display,
+    {
+        type Response = Value;
+        type Error = HandlerError;
+        type Future = Pin<Box<dyn Future<Output = Result<Value, HandlerError>> + Send>>;
+
+        fn poll_ready(
+            &mut self,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, event: LambdaEvent<Value>) -> Self::Future {
+            let (payload, context) = event.into_parts();
+
+            let payload = match serde_json::from_value(payload) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    let error = HandlerError::new("Runtime.UnmarshalError", e);
+                    return Box::pin(std::future::ready(Err(error)));
+                }
+            };
+
+            let fut = (self.f)(payload, context);
+
+            Box::pin(async move {
+                let response = fut
+                    .await
+                    .map_err(|e| HandlerError::new(error_type(&e), e))?;
+
+                serde_json::to_value(response)
+                    .map_err(|e| HandlerError::new("Runtime.MarshalError", e))
+            })
+        }
+    }
+
+    /// Name of the type of an error, without its module path and generics, like
+    /// `OutOfStock`. Boxed errors, like `lambda_runtime::Error`, erase the type of the
+    /// error they hold and are named `Box`.
+    fn error_type<E>(_error: &E) -> &'static str {
+        let type_name = std::any::type_name::<E>();
+        let type_name = type_name.split('<').next().unwrap_or(type_name);
+
+        type_name.rsplit("::").next().unwrap_or(type_name)
+    }
+
+    /// Error of a [`TypedHandler`], with the type and message of a Lambda error response.
+    ///
+    /// The `errorType` of the response is not ours to choose: `lambda_runtime` 0.5
+    /// always reports the Rust type of the handler error there, that is a reference to
+    /// this type, and its `Display` output as `errorMessage`. The type of the error
+    /// returned by the handler is therefore only found in the message, as
+    /// `ErrorType: message`.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct HandlerError {
+        pub error_type: String,
+        pub error_message: String,
+    }
+
+    impl HandlerError {
+        fn new(error_type: &str, error: impl std::fmt::Display) -> Self {
+            HandlerError {
+                error_type: error_type.to_string(),
+                error_message: error.to_string(),
+            }
+        }
+    }
+
+    impl std::fmt::Display for HandlerError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}: {}", self.error_type, self.error_message)
+        }
+    }
+
+    impl std::error::Error for HandlerError {}
+
+    /// Run an actix service handling raw lambda events.
+    ///
+    /// The service is created by `factory` on a dedicated thread, see
+    /// [`actix::event_service`](crate::actix::event_service), which means it can be
+    /// wrapped in actix middleware with `actix_service::apply`.
+    #[cfg(feature = "actix")]
+    pub async fn run_lambda_actix<F, I, S, A, B>(factory: F) -> Result<(), Error>
+    where
+        F: Fn() -> I + Send + 'static,
+        I: actix_service::IntoServiceFactory<S, LambdaEvent<A>>,
+        S: actix_service::ServiceFactory<LambdaEvent<A>, Config = (), Response = B>,
+        S::Error: std::fmt::Display,
+        S::InitError: std::fmt::Debug,
+        A: for<'de> Deserialize<'de> + Send + 'static,
+        B: Serialize + Send + 'static,
+    {
+        lambda_runtime::run(crate::actix::event_service(factory).await?).await
     }
 
     #[cfg(test)]
     mod tests {
-        use super::{run_lambda_actix, run_lambda_tower};
+        use super::run_lambda_tower;
         use lambda_runtime::{Error, LambdaEvent};
         use serde_json::{json, Value};
 
@@ -56,12 +227,91 @@ pub mod runtime {
             Ok(())
         }
 
+        #[cfg(feature = "actix")]
         #[allow(dead_code)]
         async fn main_actix() -> Result<(), Error> {
-            let func = actix_web::dev::fn_service(func);
-
-            run_lambda_actix(func).await?;
+            super::run_lambda_actix(|| actix_web::dev::fn_service(func)).await?;
             Ok(())
         }
+
+        #[derive(serde::Deserialize)]
+        struct Order {
+            quantity: u32,
+        }
+
+        #[derive(Debug)]
+        struct OutOfStock;
+
+        impl std::fmt::Display for OutOfStock {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "only 3 items left")
+            }
+        }
+
+        impl std::error::Error for OutOfStock {}
+
+        async fn order(
+            order: Order,
+            _context: lambda_runtime::Context,
+        ) -> Result<Value, OutOfStock> {
+            match order.quantity {
+                0..=3 => Ok(json!({ "shipped": order.quantity })),
+                _ => Err(OutOfStock),
+            }
+        }
+
+        async fn call(event: Value) -> Result<Value, super::HandlerError> {
+            let mut handler = super::handler(order);
+
+            tower::Service::call(
+                &mut handler,
+                LambdaEvent::new(event, lambda_runtime::Context::default()),
+            )
+            .await
+        }
+
+        #[tokio::test]
+        async fn typed_handler_response() {
+            assert_eq!(
+                call(json!({ "quantity": 2 })).await,
+                Ok(json!({ "shipped": 2 }))
+            );
+        }
+
+        #[tokio::test]
+        async fn typed_handler_invalid_payload() {
+            let error = call(json!({ "quantity": "two" })).await.unwrap_err();
+
+            assert_eq!(error.error_type, "Runtime.UnmarshalError");
+            assert!(error.error_message.contains("invalid type"));
+        }
+
+        #[tokio::test]
+        async fn typed_handler_boxed_error() {
+            let mut handler = super::handler(|_: Value, _context| async {
+                Err::<Value, Error>(Box::new(OutOfStock))
+            });
+            let error = tower::Service::call(
+                &mut handler,
+                LambdaEvent::new(json!({}), lambda_runtime::Context::default()),
+            )
+            .await
+            .unwrap_err();
+
+            // The type of the boxed error is not known
+            assert_eq!(error.error_type, "Box");
+            assert_eq!(error.to_string(), "Box: only 3 items left");
+        }
+
+        #[tokio::test]
+        async fn typed_handler_error() {
+            assert_eq!(
+                call(json!({ "quantity": 4 })).await,
+                Err(super::HandlerError {
+                    error_type: "OutOfStock".to_string(),
+                    error_message: "only 3 items left".to_string(),
+                })
+            );
+        }
     }
 }
@@ -0,0 +1,248 @@
+//! CloudWatch metrics, in the [Embedded Metric Format].
+//!
+//! [`MetricsLayer`] prints one JSON line per invocation on stdout, which lambda sends to
+//! CloudWatch Logs where the metrics are extracted, without any call to the CloudWatch
+//! API. Metrics are dimensioned by the [`MatchedRoute`] set by the adapters, so that
+//! requests to `/users/1` and `/users/2` are counted together.
+//!
+//! [Embedded Metric Format]: https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html
+
+use crate::init::ColdStart;
+use crate::route::MatchedRoute;
+use lambda_http::{Body, IntoResponse, Request, Response};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Route dimension of the requests which did not match any route.
+const UNMATCHED: &str = "unmatched";
+
+type Sink = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Layer wrapping services into [`Metrics`].
+#[derive(Clone)]
+pub struct MetricsLayer {
+    namespace: String,
+    sink: Sink,
+}
+
+impl MetricsLayer {
+    /// Emit metrics in the given CloudWatch namespace.
+    pub fn new(namespace: impl Into<String>) -> Self {
+        MetricsLayer {
+            namespace: namespace.into(),
+            sink: Arc::new(|line| println!("{}", line)),
+        }
+    }
+}
+
+impl<S> tower::layer::Layer<S> for MetricsLayer {
+    type Service = Metrics<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Metrics {
+            service,
+            namespace: self.namespace.clone(),
+            sink: self.sink.clone(),
+        }
+    }
+}
+
+/// Service emitting the `Requests`, `Latency`, `ResponseSize` and `ColdStart` metrics
+/// of every invocation, dimensioned by `Route` and by `Route` and `StatusClass`.
+pub struct Metrics<S> {
+    service: S,
+    namespace: String,
+    sink: Sink,
+}
+
+impl<S, R> tower::Service<Request> for Metrics<S>
+where
+    S: tower::Service<Request, Response = R, Error = lambda_http::Error>,
+    S::Future: Send + 'static,
+    R: IntoResponse,
+{
+    type Response = Response<Body>;
+    type Error = lambda_http::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, lambda_http::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        // Flagged on the request, where the adapter of the application finds it
+        let ColdStart(cold_start) = ColdStart::of(&mut req);
+        let start = Instant::now();
+
+        let namespace = self.namespace.clone();
+        let sink = self.sink.clone();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let result = fut.await.map(IntoResponse::into_response);
+
+            // A failed invocation ends up as a 502 from API Gateway
+            let (route, status, size) = match &result {
+                Ok(response) => (
+                    response
+                        .extensions()
+                        .get::<MatchedRoute>()
+                        .map(|r| r.0.as_str()),
+                    response.status().as_u16(),
+                    body_size(response.body()),
+                ),
+                Err(_) => (None, 502, 0),
+            };
+
+            let line = emf(&Invocation {
+                namespace: &namespace,
+                route: route.unwrap_or(UNMATCHED),
+                status,
+                latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+                size,
+                cold_start,
+            });
+            sink(&line.to_string());
+
+            result
+        })
+    }
+}
+
+struct Invocation<'a> {
+    namespace: &'a str,
+    route: &'a str,
+    status: u16,
+    latency_ms: f64,
+    size: usize,
+    cold_start: bool,
+}
+
+fn emf(invocation: &Invocation<'_>) -> Value {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    json!({
+        "_aws": {
+            "Timestamp": timestamp,
+            "CloudWatchMetrics": [{
+                "Namespace": invocation.namespace,
+                "Dimensions": [["Route"], ["Route", "StatusClass"]],
+                "Metrics": [
+                    { "Name": "Requests", "Unit": "Count" },
+                    { "Name": "Latency", "Unit": "Milliseconds" },
+                    { "Name": "ResponseSize", "Unit": "Bytes" },
+                    { "Name": "ColdStart", "Unit": "Count" },
+                ],
+            }],
+        },
+        "Route": invocation.route,
+        "StatusClass": format!("{}xx", invocation.status / 100),
+        "Requests": 1,
+        "Latency": invocation.latency_ms,
+        "ResponseSize": invocation.size,
+        "ColdStart": if invocation.cold_start { 1 } else { 0 },
+    })
+}
+
+fn body_size(body: &Body) -> usize {
+    match body {
+        Body::Empty => 0,
+        Body::Text(text) => text.len(),
+        Body::Binary(bytes) => bytes.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetricsLayer;
+    use crate::init::ColdStart;
+    use crate::route::MatchedRoute;
+    use lambda_http::{Body, Request, Response};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+    use tower::layer::Layer;
+    use tower::Service;
+
+    async fn user(_req: Request) -> Result<Response<Body>, lambda_http::Error> {
+        let mut response = Response::builder()
+            .status(404)
+            .body(Body::Text("no such user".to_string()))?;
+        response
+            .extensions_mut()
+            .insert(MatchedRoute("/users/:id".to_string()));
+
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn emit_emf_line() {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let layer = MetricsLayer {
+            namespace: "Shop".to_string(),
+            sink: {
+                let lines = lines.clone();
+                Arc::new(move |line| lines.lock().unwrap().push(line.to_string()))
+            },
+        };
+
+        let mut service = layer.layer(tower::service_fn(user));
+        for _ in 0..2 {
+            let request = http::Request::get("/users/42").body(Body::Empty).unwrap();
+            service.call(request).await.unwrap();
+        }
+
+        let lines = lines.lock().unwrap();
+        let first: Value = serde_json::from_str(&lines[0]).unwrap();
+        let second: Value = serde_json::from_str(&lines[1]).unwrap();
+
+        assert_eq!(
+            first["_aws"]["CloudWatchMetrics"][0]["Namespace"],
+            json!("Shop")
+        );
+        assert_eq!(
+            first["_aws"]["CloudWatchMetrics"][0]["Dimensions"],
+            json!([["Route"], ["Route", "StatusClass"]])
+        );
+        assert_eq!(first["Route"], "/users/:id");
+        assert_eq!(first["StatusClass"], "4xx");
+        assert_eq!(first["Requests"], 1);
+        assert_eq!(first["ResponseSize"], 12);
+        assert!(first["Latency"].is_f64());
+
+        // Cold starts only happen once per process
+        assert_eq!(second["ColdStart"], 0);
+    }
+
+    #[tokio::test]
+    async fn share_cold_start_with_the_service() {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let layer = MetricsLayer {
+            namespace: "Shop".to_string(),
+            sink: {
+                let lines = lines.clone();
+                Arc::new(move |line| lines.lock().unwrap().push(line.to_string()))
+            },
+        };
+
+        let mut service = layer.layer(tower::service_fn(|req: Request| async move {
+            let ColdStart(cold) = *req.extensions().get::<ColdStart>().unwrap();
+            Ok::<_, lambda_http::Error>(Response::new(Body::Text(cold.to_string())))
+        }));
+
+        for _ in 0..2 {
+            let request = http::Request::get("/").body(Body::Empty).unwrap();
+            let response = service.call(request).await.unwrap();
+
+            let line: Value = serde_json::from_str(lines.lock().unwrap().last().unwrap()).unwrap();
+            let cold = line["ColdStart"] == 1;
+            assert_eq!(response.into_body(), Body::Text(cold.to_string()));
+        }
+    }
+}
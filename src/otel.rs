@@ -0,0 +1,262 @@
+//! OpenTelemetry server spans around invocations.
+//!
+//! [`OpenTelemetryLayer`] continues the trace of the caller, found in the W3C
+//! `traceparent` header or in the X-Ray `X-Amzn-Trace-Id` header (set from the
+//! invocation context when API Gateway did not send it), and wraps the invocation in
+//! a server span following the HTTP semantic conventions.
+//!
+//! Lambda freezes the process as soon as the response is sent, which would leave the
+//! spans of the invocation in the buffers of the exporter. The tracer provider is
+//! flushed before the response is handed back to the runtime to prevent that.
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use lambda_http::{Body, IntoResponse, Request, Response};
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::sdk::propagation::{TextMapCompositePropagator, TraceContextPropagator};
+use opentelemetry::sdk::trace::{Tracer, TracerProvider};
+use opentelemetry::trace::{
+    FutureExt, SpanKind, StatusCode as SpanStatus, TraceContextExt, Tracer as _,
+    TracerProvider as _,
+};
+use opentelemetry::KeyValue;
+use opentelemetry_aws::XrayPropagator;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Layer wrapping services into [`OpenTelemetry`].
+#[derive(Clone)]
+pub struct OpenTelemetryLayer {
+    provider: TracerProvider,
+}
+
+impl OpenTelemetryLayer {
+    /// Create spans with a tracer of `provider`, which is flushed after every invocation.
+    pub fn new(provider: TracerProvider) -> Self {
+        OpenTelemetryLayer { provider }
+    }
+}
+
+impl<S> tower::layer::Layer<S> for OpenTelemetryLayer {
+    type Service = OpenTelemetry<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        OpenTelemetry {
+            service,
+            tracer: self.provider.tracer("aws-lambda-http-interop"),
+            provider: self.provider.clone(),
+            propagator: Arc::new(TextMapCompositePropagator::new(vec![
+                // When both are present, the W3C context extracted last wins
+                Box::new(XrayPropagator::default()),
+                Box::new(TraceContextPropagator::new()),
+            ])),
+        }
+    }
+}
+
+/// Service running each invocation inside an OpenTelemetry server span.
+pub struct OpenTelemetry<S> {
+    service: S,
+    tracer: Tracer,
+    provider: TracerProvider,
+    propagator: Arc<TextMapCompositePropagator>,
+}
+
+impl<S, R> tower::Service<Request> for OpenTelemetry<S>
+where
+    S: tower::Service<Request, Response = R, Error = lambda_http::Error>,
+    S::Future: Send + 'static,
+    R: IntoResponse,
+{
+    type Response = Response<Body>;
+    type Error = lambda_http::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, lambda_http::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        crate::xray::propagate(&mut req);
+
+        let parent = self.propagator.extract(&HeaderExtractor(req.headers()));
+
+        let mut attributes = vec![
+            KeyValue::new("http.method", req.method().to_string()),
+            KeyValue::new("http.target", req.uri().path().to_string()),
+            KeyValue::new("faas.trigger", "http"),
+        ];
+        if let Some(host) = header(req.headers(), http::header::HOST) {
+            attributes.push(KeyValue::new("http.host", host.to_string()));
+        }
+        if let Some(user_agent) = header(req.headers(), http::header::USER_AGENT) {
+            attributes.push(KeyValue::new("http.user_agent", user_agent.to_string()));
+        }
+        if let Some(context) = req.extensions().get::<lambda_runtime::Context>() {
+            attributes.push(KeyValue::new("faas.execution", context.request_id.clone()));
+        }
+
+        let builder = self
+            .tracer
+            .span_builder(format!("HTTP {}", req.method()))
+            .with_kind(SpanKind::Server)
+            .with_attributes(attributes);
+        let span = self.tracer.build_with_context(builder, &parent);
+        let cx = parent.with_span(span);
+
+        let provider = self.provider.clone();
+        let fut = self.service.call(req).with_context(cx.clone());
+
+        Box::pin(async move {
+            let result = fut.await.map(IntoResponse::into_response);
+
+            let span = cx.span();
+            match &result {
+                Ok(response) => {
+                    let status = response.status();
+                    span.set_attribute(KeyValue::new("http.status_code", status.as_u16() as i64));
+
+                    if status.is_server_error() {
+                        span.set_status(SpanStatus::Error, String::new());
+                    }
+                }
+                Err(e) => {
+                    span.record_exception(e.as_ref());
+                    span.set_status(SpanStatus::Error, e.to_string());
+                }
+            }
+            span.end();
+
+            // Exporters may block while flushing, which must not stall the runtime
+            let flushed = tokio::task::spawn_blocking(move || provider.force_flush()).await;
+            match flushed {
+                Ok(results) => {
+                    for error in results.into_iter().filter_map(Result::err) {
+                        log_error!("could not flush spans: {}", error);
+                    }
+                }
+                Err(e) => log_error!("could not flush spans: {}", e),
+            }
+
+            result
+        })
+    }
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap<HeaderValue>);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpenTelemetryLayer;
+    use async_trait::async_trait;
+    use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+    use opentelemetry::sdk::trace::TracerProvider;
+    use opentelemetry::trace::{SpanId, SpanKind, TraceId};
+    use opentelemetry::{Key, Value};
+    use std::sync::{Arc, Mutex};
+    use tower::layer::Layer;
+    use tower::Service;
+
+    #[derive(Debug, Clone, Default)]
+    struct InMemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    #[async_trait]
+    impl SpanExporter for InMemoryExporter {
+        async fn export(&mut self, batch: Vec<SpanData>) -> ExportResult {
+            self.0.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    async fn created(
+        _req: lambda_http::Request,
+    ) -> Result<lambda_http::Response<lambda_http::Body>, lambda_http::Error> {
+        let response = lambda_http::Response::builder()
+            .status(201)
+            .body(lambda_http::Body::Empty)?;
+
+        Ok(response)
+    }
+
+    async fn invoke(header: (&str, &str)) -> SpanData {
+        let exporter = InMemoryExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+
+        let mut service = OpenTelemetryLayer::new(provider).layer(tower::service_fn(created));
+
+        let request = http::Request::post("/orders")
+            .header("host", "example.com")
+            .header(header.0, header.1)
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.status(), 201);
+
+        let mut spans = exporter.0.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        spans.remove(0)
+    }
+
+    #[tokio::test]
+    async fn continue_w3c_trace() {
+        let span = invoke((
+            "traceparent",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+        ))
+        .await;
+
+        assert_eq!(span.name, "HTTP POST");
+        assert_eq!(span.span_kind, SpanKind::Server);
+        assert_eq!(
+            span.span_context.trace_id(),
+            TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap()
+        );
+        assert_eq!(
+            span.parent_span_id,
+            SpanId::from_hex("b7ad6b7169203331").unwrap()
+        );
+        assert_eq!(
+            span.attributes.get(&Key::new("http.status_code")),
+            Some(&Value::I64(201))
+        );
+        assert_eq!(
+            span.attributes.get(&Key::new("http.target")),
+            Some(&Value::from("/orders"))
+        );
+    }
+
+    #[tokio::test]
+    async fn continue_xray_trace() {
+        let span = invoke((
+            "x-amzn-trace-id",
+            "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1",
+        ))
+        .await;
+
+        assert_eq!(
+            span.span_context.trace_id(),
+            TraceId::from_hex("5759e988bd862e3fe1be46a994272793").unwrap()
+        );
+        assert_eq!(
+            span.parent_span_id,
+            SpanId::from_hex("53995c3f42cd8ad8").unwrap()
+        );
+    }
+}
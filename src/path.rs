@@ -0,0 +1,185 @@
+//! Rewriting of the request path before it reaches the framework router.
+//!
+//! Depending on how the function is exposed, the path of the event can include the
+//! API Gateway stage (`/prod/hey`) or the base path of a custom domain mapping. Routers
+//! are usually written without those prefixes, so they need to be removed from the
+//! request and added back to the redirections sent in the response.
+
+use http::header::{HeaderMap, HeaderValue, LOCATION};
+use http::uri::{PathAndQuery, Uri};
+use lambda_http::request::RequestContext;
+
+/// Which prefix, if any, to remove from the request path.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum BasePath {
+    /// Leave the path untouched.
+    #[default]
+    None,
+    /// Remove the API Gateway stage, as found in the request context.
+    Stage,
+    /// Remove a fixed prefix, like the base path of a custom domain mapping.
+    Prefix(String),
+}
+
+impl BasePath {
+    /// Remove the prefix from the request URI, returning the prefix which was removed.
+    ///
+    /// The path is only rewritten if it starts with the whole prefix, meaning `/prod`
+    /// is stripped from `/prod/hey` but not from `/production`.
+    pub(crate) fn strip(&self, req: &mut lambda_http::Request) -> Option<String> {
+        let prefix = match self {
+            BasePath::None => return None,
+            BasePath::Stage => stage(req)?,
+            BasePath::Prefix(prefix) => prefix.clone(),
+        };
+
+        let prefix = format!("/{}", prefix.trim_matches('/'));
+        if prefix == "/" {
+            return None;
+        }
+
+        let uri = req.uri();
+        let path = uri.path();
+
+        let rest = path.strip_prefix(&prefix)?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+
+        let path_and_query = match uri.query() {
+            Some(query) => format!("/{}?{}", rest.trim_start_matches('/'), query),
+            None => format!("/{}", rest.trim_start_matches('/')),
+        };
+
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).ok()?);
+        *req.uri_mut() = Uri::from_parts(parts).ok()?;
+
+        Some(prefix)
+    }
+}
+
+/// Add back a stripped prefix to the `Location` header of a response, so that
+/// redirections generated by the router are reachable from the outside.
+///
+/// Only absolute paths are rewritten, full URLs are left as is.
+pub(crate) fn prefix_location(prefix: &str, headers: &mut HeaderMap) {
+    let location = match headers.get(LOCATION).and_then(|l| l.to_str().ok()) {
+        Some(location) if location.starts_with('/') && !location.starts_with("//") => {
+            format!("{}{}", prefix, location)
+        }
+        _ => return,
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&location) {
+        headers.insert(LOCATION, value);
+    }
+}
+
+fn stage(req: &lambda_http::Request) -> Option<String> {
+    let stage = match req.extensions().get::<RequestContext>()? {
+        RequestContext::ApiGatewayV1(ctx) => ctx.stage.clone(),
+        RequestContext::ApiGatewayV2(ctx) => ctx.stage.clone(),
+        RequestContext::WebSocket(ctx) => ctx.stage.clone(),
+        RequestContext::Alb(_) => None,
+    };
+
+    // The default stage of HTTP APIs is not part of the path
+    stage.filter(|stage| stage != "$default")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prefix_location, BasePath};
+    use http::header::{HeaderMap, LOCATION};
+    use lambda_http::request::RequestContext;
+    use serde_json::json;
+
+    fn request(uri: &str, stage: &str) -> lambda_http::Request {
+        let mut request = http::Request::get(uri)
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+
+        // Context of a captured event, see `tests/fixtures`
+        let context = RequestContext::ApiGatewayV2(
+            serde_json::from_value(json!({
+                "accountId": "123456789012",
+                "apiId": "aaaaaaaaaa",
+                "domainName": "aaaaaaaaaa.execute-api.us-west-2.amazonaws.com",
+                "domainPrefix": "aaaaaaaaaa",
+                "http": {
+                    "method": "GET",
+                    "path": "/",
+                    "protocol": "HTTP/1.1",
+                    "sourceIp": "1.2.3.4",
+                    "userAgent": "curl/7.58.0"
+                },
+                "requestId": "LV7fzho-PHcEJPw=",
+                "routeKey": "$default",
+                "stage": stage,
+                "time": "21/Apr/2020:15:08:21 +0000",
+                "timeEpoch": 1587481701067u64
+            }))
+            .unwrap(),
+        );
+        request.extensions_mut().insert(context);
+
+        request
+    }
+
+    #[test]
+    fn strip_stage() {
+        let mut req = request("https://example.com/prod/hey?name=you", "prod");
+
+        let prefix = BasePath::Stage.strip(&mut req);
+
+        assert_eq!(prefix.as_deref(), Some("/prod"));
+        assert_eq!(req.uri(), "https://example.com/hey?name=you");
+    }
+
+    #[test]
+    fn ignore_default_stage() {
+        let mut req = request("/$default/hey", "$default");
+
+        assert_eq!(BasePath::Stage.strip(&mut req), None);
+        assert_eq!(req.uri(), "/$default/hey");
+    }
+
+    #[test]
+    fn strip_prefix() {
+        let mut req = request("/api/v1", "prod");
+
+        let prefix = BasePath::Prefix("api/v1/".to_string()).strip(&mut req);
+
+        assert_eq!(prefix.as_deref(), Some("/api/v1"));
+        assert_eq!(req.uri(), "/");
+    }
+
+    #[test]
+    fn only_strip_whole_segments() {
+        let mut req = request("/production/hey", "prod");
+
+        assert_eq!(BasePath::Stage.strip(&mut req), None);
+        assert_eq!(req.uri(), "/production/hey");
+    }
+
+    #[test]
+    fn prefix_relative_location() {
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, "/login".parse().unwrap());
+
+        prefix_location("/prod", &mut headers);
+
+        assert_eq!(headers[LOCATION], "/prod/login");
+    }
+
+    #[test]
+    fn keep_absolute_location() {
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, "https://example.com/login".parse().unwrap());
+
+        prefix_location("/prod", &mut headers);
+
+        assert_eq!(headers[LOCATION], "https://example.com/login");
+    }
+}
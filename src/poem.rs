@@ -0,0 +1,96 @@
+use crate::convert::{FromLambdaBody, IntoLambdaBody};
+use poem::endpoint::{Endpoint, IntoEndpoint};
+use poem::http::uri::Scheme;
+use poem::web::{LocalAddr, RemoteAddr};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+pub async fn run<E>(endpoint: E) -> Result<(), lambda_http::Error>
+where
+    E: IntoEndpoint,
+    E::Endpoint: 'static,
+{
+    lambda_http::run(service(endpoint).await).await
+}
+
+pub struct PoemService<E> {
+    endpoint: Arc<E>,
+}
+
+pub async fn service<E>(endpoint: E) -> PoemService<E::Endpoint>
+where
+    E: IntoEndpoint,
+{
+    PoemService {
+        endpoint: Arc::new(endpoint.into_endpoint()),
+    }
+}
+
+impl<E> tower::Service<lambda_http::Request> for PoemService<E>
+where
+    E: Endpoint + 'static,
+{
+    type Response = lambda_http::Response<lambda_http::Body>;
+    type Error = lambda_http::Error;
+
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: lambda_http::Request) -> Self::Future {
+        let req = req.map(hyper::Body::from_lambda_body);
+
+        // Lambda invocations do not come from a socket, and API Gateway as well as
+        // ALB only ever talk HTTPS to the outside world.
+        let req = poem::Request::from((
+            req,
+            LocalAddr::default(),
+            RemoteAddr::default(),
+            Scheme::HTTPS,
+        ));
+
+        let endpoint = self.endpoint.clone();
+
+        Box::pin(async move {
+            let response: hyper::Response<hyper::Body> = endpoint.get_response(req).await.into();
+
+            let (parts, body) = response.into_parts();
+            let body = body.into_lambda_body().await?;
+
+            Ok(lambda_http::Response::from_parts(parts, body))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::{handler, Route};
+    use tower::Service;
+
+    #[handler]
+    fn hello() -> &'static str {
+        "hello"
+    }
+
+    #[tokio::test]
+    async fn forward_request_to_endpoint() {
+        let app = Route::new().at("/hello", poem::get(hello));
+        let mut service = super::service(app).await;
+
+        let request = http::Request::get("https://www.rust-lang.org/hello")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+
+        let response = service.call(request).await.unwrap();
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(
+            response.into_body(),
+            lambda_http::Body::Binary("hello".as_bytes().to_vec())
+        );
+    }
+}
@@ -0,0 +1,576 @@
+//! Local reverse proxy in front of a lambda function.
+//!
+//! This is the proxy mode of the local server, see [`serve`]. Unlike
+//! [`hyper::serve`](crate::hyper::serve), which calls the service in-process, incoming
+//! HTTP requests are serialized into the JSON event an AWS event source would
+//! send, and handed to a real bootstrap binary through a [`RuntimeEmulator`]. The JSON
+//! response of the function is then translated back into an HTTP response.
+//!
+//! This goes through the same serialization as a deployed function does, so bugs in
+//! the handling of base64 bodies, headers or cookies show up locally.
+
+use crate::emulator::{InvocationError, RuntimeEmulator};
+use hyper::server::conn::AddrStream;
+use hyper::{Body, Request, Response, Server, StatusCode};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Which AWS service the events are emulated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSource {
+    /// Application Load Balancer, with single value headers.
+    Alb,
+    /// API Gateway REST API, payload format 1.0.
+    ApiGatewayV1,
+    /// API Gateway HTTP API, payload format 2.0.
+    ApiGatewayV2,
+    /// Lambda function URL, which uses the payload format 2.0 as well.
+    FunctionUrl,
+}
+
+impl FromStr for EventSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alb" => Ok(EventSource::Alb),
+            "apigw-v1" => Ok(EventSource::ApiGatewayV1),
+            "apigw-v2" => Ok(EventSource::ApiGatewayV2),
+            "url" => Ok(EventSource::FunctionUrl),
+            other => Err(format!(
+                "unknown event source {}, expected one of alb, apigw-v1, apigw-v2 or url",
+                other
+            )),
+        }
+    }
+}
+
+/// Proxy requests on `addr` to the function `proxy` invokes.
+///
+/// The function itself is started separately, with the environment given by
+/// [`RuntimeEmulator::env`].
+pub async fn serve(addr: SocketAddr, proxy: Proxy) -> Result<(), hyper::Error> {
+    Server::try_bind(&addr)?.serve(MakeProxy(proxy)).await
+}
+
+/// Creates a [`Proxy`] for every connection, which knows the address of the client.
+struct MakeProxy(Proxy);
+
+impl<'t> tower::Service<&'t AddrStream> for MakeProxy {
+    type Response = Proxy;
+    type Error = Infallible;
+    type Future = std::future::Ready<Result<Proxy, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, conn: &'t AddrStream) -> Self::Future {
+        std::future::ready(Ok(self.0.clone().with_peer(conn.remote_addr())))
+    }
+}
+
+/// Service turning HTTP requests into invocations of a lambda function.
+#[derive(Clone)]
+pub struct Proxy {
+    source: EventSource,
+    emulator: Arc<RuntimeEmulator>,
+    peer: Option<SocketAddr>,
+    recorder: Option<Arc<Recorder>>,
+}
+
+impl Proxy {
+    pub fn new(source: EventSource, emulator: Arc<RuntimeEmulator>) -> Self {
+        Proxy {
+            source,
+            emulator,
+            peer: None,
+            recorder: None,
+        }
+    }
+
+    /// Save every event and the response of the function to `dir`, one JSON file per
+    /// invocation, to be replayed with `testing::replay`.
+    ///
+    /// Invocations which failed are not recorded.
+    pub fn with_recording(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.recorder = Some(Arc::new(Recorder {
+            dir: dir.into(),
+            next: AtomicU64::new(0),
+        }));
+        self
+    }
+
+    /// Address of the client, given to the function as the source ip.
+    pub fn with_peer(mut self, peer: SocketAddr) -> Self {
+        self.peer = Some(peer);
+        self
+    }
+}
+
+impl tower::Service<Request<Body>> for Proxy {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let source = self.source;
+        let emulator = self.emulator.clone();
+        let recorder = self.recorder.clone();
+        let peer = self
+            .peer
+            .map(|peer| peer.ip())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(e) => return Ok(text_response(StatusCode::BAD_REQUEST, e.to_string())),
+            };
+
+            let event = request_to_event(source, &Request::from_parts(parts, ()), &body, peer);
+
+            let response = match emulator.invoke(event.to_string()).await {
+                Ok(payload) => {
+                    if let Some(recorder) = recorder {
+                        recorder.record(event, &payload);
+                    }
+
+                    payload_to_response(source, &payload)
+                }
+                // API Gateway hides the error from the client, so should we
+                Err(InvocationError::Function(_)) | Err(InvocationError::Dropped) => {
+                    text_response(StatusCode::BAD_GATEWAY, "Internal Server Error".to_string())
+                }
+            };
+
+            Ok(response)
+        })
+    }
+}
+
+/// Writes invocations to a directory, named so that they sort chronologically.
+struct Recorder {
+    dir: PathBuf,
+    next: AtomicU64,
+}
+
+impl Recorder {
+    fn record(&self, event: Value, payload: &[u8]) {
+        let response = serde_json::from_slice(payload)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(payload).into_owned()));
+        let recording = json!({ "event": event, "response": response });
+
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let name = format!(
+            "{}-{:06}.json",
+            started,
+            self.next.fetch_add(1, Ordering::SeqCst)
+        );
+
+        // Recording is a debugging aid, it should not make the request fail
+        let result = std::fs::create_dir_all(&self.dir).and_then(|()| {
+            let json = serde_json::to_vec_pretty(&recording)?;
+            std::fs::write(self.dir.join(name), json)
+        });
+
+        if let Err(e) = result {
+            log_error!(
+                "could not record invocation in {}: {}",
+                self.dir.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Serialize a request the way `source` would send it to a function.
+pub(crate) fn request_to_event(
+    source: EventSource,
+    req: &Request<()>,
+    body: &[u8],
+    peer: IpAddr,
+) -> Value {
+    let request_id = format!("proxy-{}", NEXT_REQUEST_ID.fetch_add(1, Ordering::SeqCst));
+    let method = req.method().as_str();
+    let path = req.uri().path();
+    let raw_query = req.uri().query().unwrap_or_default();
+
+    let host = req
+        .headers()
+        .get(http::header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("localhost")
+        .to_string();
+
+    let mut headers: Vec<(String, String)> = req
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    // AWS always forwards the host, which HTTP/1.0 clients may not send
+    if !req.headers().contains_key(http::header::HOST) {
+        headers.push(("host".to_string(), host.clone()));
+    }
+    headers.push(("x-forwarded-for".to_string(), peer.to_string()));
+    headers.push(("x-forwarded-proto".to_string(), "http".to_string()));
+
+    // Text bodies are sent as is, anything else is base64 encoded
+    let (body, is_base64_encoded) = match std::str::from_utf8(body) {
+        Ok(text) => (Value::String(text.to_string()), false),
+        Err(_) => (Value::String(base64::encode(body)), true),
+    };
+
+    let query = query_pairs(raw_query);
+
+    let time_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    match source {
+        EventSource::Alb => json!({
+            "requestContext": {
+                "elb": {
+                    "targetGroupArn": "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/proxy/0123456789abcdef"
+                }
+            },
+            "httpMethod": method,
+            "path": path,
+            // ALB forwards the query string parameters as they were sent
+            "queryStringParameters": single_values(raw_query_pairs(raw_query)),
+            "headers": single_values(headers.into_iter()),
+            "isBase64Encoded": is_base64_encoded,
+            "body": body,
+        }),
+        EventSource::ApiGatewayV1 => json!({
+            "resource": "/{proxy+}",
+            "path": path,
+            "httpMethod": method,
+            "headers": single_values(headers.iter().cloned()),
+            "multiValueHeaders": multi_values(headers.into_iter()),
+            "queryStringParameters": single_values(query.iter().cloned()),
+            "multiValueQueryStringParameters": multi_values(query.into_iter()),
+            "pathParameters": { "proxy": path.trim_start_matches('/') },
+            "stageVariables": null,
+            "requestContext": {
+                "resourceId": "proxy",
+                "resourcePath": "/{proxy+}",
+                "httpMethod": method,
+                "requestId": request_id,
+                "accountId": "123456789012",
+                "stage": "local",
+                "path": path,
+                "requestTimeEpoch": time_epoch,
+                "domainName": host,
+                "identity": {
+                    "sourceIp": peer.to_string(),
+                },
+                "apiId": "local",
+            },
+            "body": body,
+            "isBase64Encoded": is_base64_encoded,
+        }),
+        EventSource::ApiGatewayV2 | EventSource::FunctionUrl => {
+            let cookies: Vec<String> = headers
+                .iter()
+                .filter(|(name, _)| name == "cookie")
+                .flat_map(|(_, value)| value.split("; ").map(str::to_string))
+                .collect();
+            let headers = headers.into_iter().filter(|(name, _)| name != "cookie");
+
+            let mut query_parameters = Map::new();
+            for (name, value) in query {
+                match query_parameters.get_mut(&name) {
+                    // HTTP APIs join repeated parameters with commas
+                    Some(Value::String(existing)) => {
+                        existing.push(',');
+                        existing.push_str(&value);
+                    }
+                    _ => {
+                        query_parameters.insert(name, Value::String(value));
+                    }
+                }
+            }
+
+            json!({
+                "version": "2.0",
+                "routeKey": "$default",
+                "rawPath": path,
+                "rawQueryString": raw_query,
+                "cookies": cookies,
+                "headers": single_values(headers),
+                "queryStringParameters": query_parameters,
+                "requestContext": {
+                    "accountId": "123456789012",
+                    "apiId": "local",
+                    "domainName": host,
+                    "domainPrefix": "local",
+                    "http": {
+                        "method": method,
+                        "path": path,
+                        "protocol": "HTTP/1.1",
+                        "sourceIp": peer.to_string(),
+                        "userAgent": "",
+                    },
+                    "requestId": request_id,
+                    "routeKey": "$default",
+                    "stage": "$default",
+                    "timeEpoch": time_epoch,
+                },
+                "body": body,
+                "isBase64Encoded": is_base64_encoded,
+            })
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LambdaResponse {
+    status_code: u16,
+    #[serde(default)]
+    headers: Option<HashMap<String, String>>,
+    #[serde(default)]
+    multi_value_headers: Option<HashMap<String, Vec<String>>>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    is_base64_encoded: bool,
+    #[serde(default)]
+    cookies: Vec<String>,
+}
+
+/// Translate the payload returned by a function back into an HTTP response.
+pub(crate) fn payload_to_response(source: EventSource, payload: &[u8]) -> Response<Body> {
+    let response = match serde_json::from_slice::<LambdaResponse>(payload) {
+        Ok(response) => response,
+        // HTTP APIs accept any JSON as a response, and send it as is
+        Err(_) if matches!(source, EventSource::ApiGatewayV2 | EventSource::FunctionUrl) => {
+            return Response::builder()
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(payload.to_vec()))
+                .expect("json response should be valid")
+        }
+        Err(e) => {
+            return text_response(
+                StatusCode::BAD_GATEWAY,
+                format!("invalid lambda response: {}", e),
+            )
+        }
+    };
+
+    let mut builder = Response::builder().status(response.status_code);
+
+    let multi_value_headers = response.multi_value_headers.unwrap_or_default();
+    for (name, values) in &multi_value_headers {
+        for value in values {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+    }
+    for (name, value) in response.headers.unwrap_or_default() {
+        if !multi_value_headers.contains_key(&name) {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+    }
+    for cookie in &response.cookies {
+        builder = builder.header(http::header::SET_COOKIE, cookie.as_str());
+    }
+
+    let body = match response.body {
+        Some(body) if response.is_base64_encoded => match base64::decode(&body) {
+            Ok(bytes) => Body::from(bytes),
+            Err(e) => {
+                return text_response(
+                    StatusCode::BAD_GATEWAY,
+                    format!("invalid base64 body: {}", e),
+                )
+            }
+        },
+        Some(body) => Body::from(body),
+        None => Body::empty(),
+    };
+
+    builder.body(body).unwrap_or_else(|e| {
+        text_response(
+            StatusCode::BAD_GATEWAY,
+            format!("invalid lambda response: {}", e),
+        )
+    })
+}
+
+fn text_response(status: StatusCode, message: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "text/plain")
+        .body(Body::from(message))
+        .expect("text response should be valid")
+}
+
+/// Query string parameters, as they were sent by the client.
+fn raw_query_pairs(query: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (name.to_string(), value.to_string())
+        })
+}
+
+/// Query string parameters, decoded like API Gateway does.
+fn query_pairs(query: &str) -> Vec<(String, String)> {
+    raw_query_pairs(query)
+        .map(|(name, value)| (decode(&name), decode(&value)))
+        .collect()
+}
+
+fn decode(component: &str) -> String {
+    let component = component.replace('+', " ");
+
+    percent_decode_str(&component)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// The last value wins, which is what single value headers and parameters look like.
+fn single_values(pairs: impl Iterator<Item = (String, String)>) -> Value {
+    let mut map = Map::new();
+
+    for (name, value) in pairs {
+        map.insert(name, Value::String(value));
+    }
+
+    Value::Object(map)
+}
+
+fn multi_values(pairs: impl Iterator<Item = (String, String)>) -> Value {
+    let mut map = Map::new();
+
+    for (name, value) in pairs {
+        match map.entry(name).or_insert_with(|| Value::Array(vec![])) {
+            Value::Array(values) => values.push(Value::String(value)),
+            _ => unreachable!("only arrays are inserted"),
+        }
+    }
+
+    Value::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{payload_to_response, request_to_event, EventSource};
+    use hyper::Request;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9));
+
+    fn request() -> Request<()> {
+        Request::post("/echo?name=Jane%20Doe&tag=a&tag=b")
+            .header("host", "localhost:3000")
+            .header("cookie", "a=1; b=2")
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn events_are_understood_by_lambda_http() {
+        let sources = [
+            EventSource::Alb,
+            EventSource::ApiGatewayV1,
+            EventSource::ApiGatewayV2,
+            EventSource::FunctionUrl,
+        ];
+
+        for source in sources {
+            let event = request_to_event(source, &request(), &[0, 1, 2, 255], PEER);
+            let req = lambda_http::request::from_str(&event.to_string())
+                .unwrap_or_else(|e| panic!("{:?}: {}", source, e));
+
+            // lambda_http puts the stage of REST APIs in the path, like it would on lambda
+            let path = match source {
+                EventSource::ApiGatewayV1 => "/local/echo",
+                _ => "/echo",
+            };
+
+            assert_eq!(req.method(), "POST", "{:?}", source);
+            assert_eq!(req.uri().path(), path, "{:?}", source);
+            assert_eq!(
+                req.body(),
+                &lambda_http::Body::Binary(vec![0, 1, 2, 255]),
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn api_gateway_v2_event() {
+        let event = request_to_event(EventSource::ApiGatewayV2, &request(), b"hey", PEER);
+
+        assert_eq!(event["rawQueryString"], "name=Jane%20Doe&tag=a&tag=b");
+        assert_eq!(event["queryStringParameters"]["name"], "Jane Doe");
+        assert_eq!(event["queryStringParameters"]["tag"], "a,b");
+        assert_eq!(event["cookies"], serde_json::json!(["a=1", "b=2"]));
+        assert_eq!(event["requestContext"]["http"]["sourceIp"], "203.0.113.9");
+        assert_eq!(event["body"], "hey");
+        assert_eq!(event["isBase64Encoded"], false);
+    }
+
+    #[tokio::test]
+    async fn decode_base64_response() {
+        let payload = br#"{
+            "statusCode": 201,
+            "headers": { "content-type": "application/octet-stream" },
+            "multiValueHeaders": {},
+            "body": "AAEC/w==",
+            "isBase64Encoded": true,
+            "cookies": ["session=abc"]
+        }"#;
+
+        let response = payload_to_response(EventSource::ApiGatewayV2, payload);
+
+        assert_eq!(response.status(), 201);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/octet-stream"
+        );
+        assert_eq!(response.headers()["set-cookie"], "session=abc");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], &[0, 1, 2, 255]);
+    }
+
+    #[tokio::test]
+    async fn plain_json_response_of_http_api() {
+        let response = payload_to_response(EventSource::FunctionUrl, br#"{"hello":"world"}"#);
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/json");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], br#"{"hello":"world"}"#);
+    }
+}
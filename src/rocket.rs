@@ -0,0 +1,113 @@
+use crate::convert::{FromLambdaBody, IntoLambdaBody};
+use bytes::Bytes;
+use rocket::http::{Header, Method};
+use rocket::local::asynchronous::Client;
+use rocket::{Build, Rocket};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+pub async fn run(rocket: Rocket<Build>) -> Result<(), lambda_http::Error> {
+    lambda_http::run(service(rocket).await?).await
+}
+
+/// Dispatch lambda requests to a rocket application, using rocket's local client.
+///
+/// Rocket doesn't expose its router as a service, but its local client goes through
+/// the exact same request lifecycle (fairings, guards, catchers) without any socket.
+pub struct RocketService {
+    client: Arc<Client>,
+}
+
+pub async fn service(rocket: Rocket<Build>) -> Result<RocketService, rocket::Error> {
+    // Cookies are not kept in between invocations, each one is its own client session
+    let client = Client::untracked(rocket).await?;
+
+    Ok(RocketService {
+        client: Arc::new(client),
+    })
+}
+
+impl tower::Service<lambda_http::Request> for RocketService {
+    type Response = lambda_http::Response<lambda_http::Body>;
+    type Error = lambda_http::Error;
+
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: lambda_http::Request) -> Self::Future {
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+
+            let method: Method = parts
+                .method
+                .as_str()
+                .parse()
+                .map_err(|_| format!("unsupported HTTP method {}", parts.method))?;
+
+            // The local client only accepts origin-form URIs
+            let uri = parts
+                .uri
+                .path_and_query()
+                .map(|p| p.as_str().to_owned())
+                .unwrap_or_else(|| "/".to_owned());
+
+            let mut request = client.req(method, uri);
+
+            for (name, value) in parts.headers.iter() {
+                if let Ok(value) = value.to_str() {
+                    request.add_header(Header::new(name.as_str().to_owned(), value.to_owned()));
+                }
+            }
+
+            request.set_body(Bytes::from_lambda_body(body));
+
+            let response = request.dispatch().await;
+
+            let mut builder = lambda_http::Response::builder().status(response.status().code);
+
+            for header in response.headers().iter() {
+                builder = builder.header(header.name().as_str(), header.value());
+            }
+
+            let bytes = response.into_bytes().await.unwrap_or_default();
+            let body = Bytes::from(bytes).into_lambda_body().await?;
+
+            Ok(builder.body(body)?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::Service;
+
+    #[rocket::get("/hello/<name>")]
+    fn hello(name: &str) -> String {
+        format!("Hello, {}!", name)
+    }
+
+    #[tokio::test]
+    async fn dispatch_request_to_rocket() {
+        let rocket = rocket::build().mount("/", rocket::routes![hello]);
+        let mut service = super::service(rocket).await.unwrap();
+
+        let request = http::Request::get("https://www.rust-lang.org/hello/lambda")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+
+        let response = service.call(request).await.unwrap();
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(
+            response.into_body(),
+            lambda_http::Body::Binary("Hello, lambda!".as_bytes().to_vec())
+        );
+    }
+}
@@ -0,0 +1,11 @@
+//! Route which handled a request, as known by the framework router.
+
+/// Pattern of the route which handled a request, like `/users/:id` or `/users/{id}`.
+///
+/// The adapters put it in the extensions of the lambda response, so layers around
+/// them can label requests with a bounded number of values, unlike the request path.
+/// It comes from [`MatchedPath`](axum::extract::MatchedPath) with axum, and with actix
+/// from `match_pattern()`, once the [`RecordRoute`](crate::actix::RecordRoute)
+/// middleware wraps the application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedRoute(pub String);
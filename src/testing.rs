@@ -0,0 +1,390 @@
+//! Helpers to test lambda functions locally, with the JSON events AWS would send.
+//!
+//! Events are deserialized the same way `lambda_http` does it, and responses are
+//! serialized into the payload API Gateway or ALB would receive from the function.
+//! Tests can then assert on the exact JSON, base64 encoding and headers included.
+//!
+//! Invocations recorded by the local proxy can be replayed with [`replay`], turning
+//! exploratory sessions into regression tests.
+
+use aws_lambda_events::alb::AlbTargetGroupResponse;
+use aws_lambda_events::apigw::{ApiGatewayProxyResponse, ApiGatewayV2httpResponse};
+use http::header::SET_COOKIE;
+use lambda_http::request::RequestContext;
+use lambda_http::IntoResponse;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Invoke a service with a JSON event, returning the serialized lambda response.
+///
+/// Works with any service accepted by `lambda_http::run`, including the actix,
+/// axum and hyper adapters of this crate.
+pub async fn invoke<S, R>(service: &mut S, event: &str) -> Result<Value, lambda_http::Error>
+where
+    S: tower::Service<lambda_http::Request, Response = R, Error = lambda_http::Error>,
+    R: IntoResponse,
+{
+    let mut request = lambda_http::request::from_str(event)?;
+
+    // lambda_http always gives the invocation context to handlers
+    if request
+        .extensions()
+        .get::<lambda_runtime::Context>()
+        .is_none()
+    {
+        request
+            .extensions_mut()
+            .insert(lambda_runtime::Context::default());
+    }
+
+    let context = request.extensions().get::<RequestContext>().cloned();
+
+    std::future::poll_fn(|cx| service.poll_ready(cx)).await?;
+    let response = service.call(request).await?.into_response();
+
+    Ok(serialize_response(context.as_ref(), response))
+}
+
+/// Recorded invocation whose response changed when replayed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub recording: PathBuf,
+    /// One line per value which differs, with its path in the response.
+    pub differences: Vec<String>,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:", self.recording.display())?;
+
+        for difference in &self.differences {
+            write!(f, "\n  {}", difference)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Invoke a service with every event recorded in `dir`, in the order they were
+/// recorded, and compare the responses with the recorded ones.
+///
+/// ```ignore
+/// let mismatches = testing::replay(&mut service, "tests/recordings").await?;
+/// assert!(mismatches.is_empty(), "{:#?}", mismatches);
+/// ```
+pub async fn replay<S, R>(
+    service: &mut S,
+    dir: impl AsRef<Path>,
+) -> Result<Vec<Mismatch>, lambda_http::Error>
+where
+    S: tower::Service<lambda_http::Request, Response = R, Error = lambda_http::Error>,
+    R: IntoResponse,
+{
+    let mut recordings: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    recordings.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+    recordings.sort();
+
+    let mut mismatches = vec![];
+
+    for recording in recordings {
+        let recorded: Value = serde_json::from_slice(&std::fs::read(&recording)?)?;
+
+        let response = invoke(service, &recorded["event"].to_string()).await?;
+
+        let mut differences = vec![];
+        diff("$", &recorded["response"], &response, &mut differences);
+
+        if !differences.is_empty() {
+            mismatches.push(Mismatch {
+                recording,
+                differences,
+            });
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// Describe the differences between two JSON values, missing keys being null.
+fn diff(path: &str, expected: &Value, actual: &Value, differences: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            let added = actual.keys().filter(|key| !expected.contains_key(*key));
+
+            for key in expected.keys().chain(added) {
+                diff(
+                    &format!("{}.{}", path, key),
+                    expected.get(key).unwrap_or(&Value::Null),
+                    actual.get(key).unwrap_or(&Value::Null),
+                    differences,
+                );
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) if expected.len() == actual.len() => {
+            for (i, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                diff(&format!("{}[{}]", path, i), expected, actual, differences);
+            }
+        }
+        (expected, actual) if expected != actual => {
+            differences.push(format!("{}: expected {}, got {}", path, expected, actual));
+        }
+        _ => {}
+    }
+}
+
+/// Serialize a response in the format expected by the event source the request came from.
+///
+/// This builds the same `aws_lambda_events` responses as `lambda_http`, which does not
+/// expose its own conversion, so the JSON is exactly the payload sent back to AWS.
+pub(crate) fn serialize_response(
+    context: Option<&RequestContext>,
+    response: lambda_http::Response<lambda_http::Body>,
+) -> Value {
+    let (parts, body) = response.into_parts();
+    let status_code = parts.status.as_u16() as i64;
+
+    let (is_base64_encoded, body) = match body {
+        lambda_http::Body::Empty => (false, None),
+        body @ lambda_http::Body::Text(_) => (false, Some(body)),
+        body @ lambda_http::Body::Binary(_) => (true, Some(body)),
+    };
+
+    let mut headers = parts.headers;
+
+    let response = match context {
+        Some(RequestContext::ApiGatewayV2(_)) => {
+            // API Gateway v2 expects the cookies on their own
+            let cookies = headers
+                .get_all(SET_COOKIE)
+                .iter()
+                .map(|v| v.to_str().unwrap_or_default().to_string())
+                .collect();
+            headers.remove(SET_COOKIE);
+
+            serde_json::to_value(ApiGatewayV2httpResponse {
+                body,
+                status_code,
+                is_base64_encoded: Some(is_base64_encoded),
+                cookies,
+                headers: headers.clone(),
+                multi_value_headers: headers,
+            })
+        }
+        Some(RequestContext::Alb(_)) => serde_json::to_value(AlbTargetGroupResponse {
+            body,
+            status_code,
+            is_base64_encoded,
+            headers: headers.clone(),
+            multi_value_headers: headers,
+            status_description: Some(format!(
+                "{} {}",
+                status_code,
+                parts.status.canonical_reason().unwrap_or_default()
+            )),
+        }),
+        // API Gateway v1 and WebSocket APIs, which is also what lambda_http defaults to
+        _ => serde_json::to_value(ApiGatewayProxyResponse {
+            body,
+            status_code,
+            is_base64_encoded: Some(is_base64_encoded),
+            headers: headers.clone(),
+            multi_value_headers: headers,
+        }),
+    };
+
+    response.expect("lambda responses are always serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    async fn hello(
+        request: lambda_http::Request,
+    ) -> Result<lambda_http::Response<lambda_http::Body>, lambda_http::Error> {
+        let response = lambda_http::Response::builder()
+            .status(201)
+            .header("content-type", "text/plain")
+            .header("set-cookie", "session=abc")
+            .body(lambda_http::Body::Text(format!(
+                "{} {}",
+                request.method(),
+                request.uri().path()
+            )))?;
+
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn invoke_with_api_gateway_v1_event() {
+        let mut service = tower::service_fn(hello);
+
+        let response = super::invoke(
+            &mut service,
+            include_str!("../tests/fixtures/apigw-v1.json"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response,
+            json!({
+                "statusCode": 201,
+                "headers": {
+                    "content-type": "text/plain",
+                    "set-cookie": "session=abc"
+                },
+                "multiValueHeaders": {
+                    "content-type": ["text/plain"],
+                    "set-cookie": ["session=abc"]
+                },
+                "body": "POST /testStage/echo",
+                "isBase64Encoded": false
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn invoke_with_api_gateway_v2_event() {
+        let mut service = tower::service_fn(hello);
+
+        let response = super::invoke(
+            &mut service,
+            include_str!("../tests/fixtures/apigw-v2.json"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response,
+            json!({
+                "statusCode": 201,
+                "headers": { "content-type": "text/plain" },
+                "multiValueHeaders": { "content-type": ["text/plain"] },
+                "body": "POST /echo",
+                "isBase64Encoded": false,
+                "cookies": ["session=abc"]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn invoke_with_alb_event() {
+        let mut service = tower::service_fn(hello);
+
+        let response = super::invoke(&mut service, include_str!("../tests/fixtures/alb.json"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response,
+            json!({
+                "statusCode": 201,
+                "statusDescription": "201 Created",
+                "headers": {
+                    "content-type": "text/plain",
+                    "set-cookie": "session=abc"
+                },
+                "multiValueHeaders": {
+                    "content-type": ["text/plain"],
+                    "set-cookie": ["session=abc"]
+                },
+                "body": "GET /echo",
+                "isBase64Encoded": false
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn omit_empty_body() {
+        let mut service = tower::service_fn(|_req| async {
+            lambda_http::Response::builder()
+                .status(204)
+                .body(lambda_http::Body::Empty)
+                .map_err(lambda_http::Error::from)
+        });
+
+        let response = super::invoke(
+            &mut service,
+            include_str!("../tests/fixtures/apigw-v1-base64.json"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response,
+            json!({
+                "statusCode": 204,
+                "headers": {},
+                "multiValueHeaders": {},
+                "isBase64Encoded": false
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_recorded_invocations() {
+        let dir = std::env::temp_dir().join(format!("replay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let event: serde_json::Value =
+            serde_json::from_str(include_str!("../tests/fixtures/apigw-v1.json")).unwrap();
+        let recording = |status: u16| {
+            json!({
+                "event": event,
+                "response": {
+                    "statusCode": status,
+                    "headers": {
+                        "content-type": "text/plain",
+                        "set-cookie": "session=abc"
+                    },
+                    "multiValueHeaders": {
+                        "content-type": ["text/plain"],
+                        "set-cookie": ["session=abc"]
+                    },
+                    "body": "POST /testStage/echo",
+                    "isBase64Encoded": false
+                }
+            })
+            .to_string()
+        };
+        std::fs::write(dir.join("1-000000.json"), recording(201)).unwrap();
+        std::fs::write(dir.join("1-000001.json"), recording(200)).unwrap();
+
+        let mut service = tower::service_fn(hello);
+        let mismatches = super::replay(&mut service, &dir).await.unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            mismatches,
+            vec![super::Mismatch {
+                recording: dir.join("1-000001.json"),
+                differences: vec!["$.statusCode: expected 200, got 201".to_string()],
+            }]
+        );
+    }
+
+    #[cfg(feature = "axum")]
+    #[tokio::test]
+    async fn invoke_axum_service() {
+        let router = axum::Router::new().route(
+            "/echo",
+            axum::routing::put(|body: axum::body::Bytes| async move { body }),
+        );
+        let mut service = crate::axum::service(router).await;
+
+        let response = super::invoke(
+            &mut service,
+            include_str!("../tests/fixtures/apigw-v2-base64.json"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response["statusCode"], 200);
+        assert_eq!(response["body"], "AAEC/w==");
+        assert_eq!(response["isBase64Encoded"], true);
+    }
+}
@@ -0,0 +1,237 @@
+//! [`tracing`](::tracing) spans around invocations.
+//!
+//! [`TraceLayer`] wraps any service accepted by `lambda_http::run`: the adapters of
+//! this crate, as well as the services given to [`hyper::serve`](crate::hyper::serve)
+//! for local development. Each invocation runs inside a span carrying the identifiers
+//! needed to find it in CloudWatch and X-Ray, and records its outcome.
+
+use ::tracing::field::Empty;
+use ::tracing::{info_span, Instrument, Span};
+use lambda_http::request::RequestContext;
+use lambda_http::{Body, IntoResponse, Request, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Layer wrapping services into [`Trace`].
+#[derive(Debug, Clone, Default)]
+pub struct TraceLayer {
+    _priv: (),
+}
+
+impl TraceLayer {
+    pub fn new() -> Self {
+        TraceLayer::default()
+    }
+}
+
+impl<S> tower::layer::Layer<S> for TraceLayer {
+    type Service = Trace<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Trace { service }
+    }
+}
+
+/// Service running each invocation inside a `lambda_invocation` span.
+///
+/// The span has the `request_id`, `xray_trace_id`, `http.method`, `http.path` and
+/// `event_source` fields, and records `http.status_code` and `latency_ms` once the
+/// response is ready.
+#[derive(Debug, Clone)]
+pub struct Trace<S> {
+    service: S,
+}
+
+impl<S, R> tower::Service<Request> for Trace<S>
+where
+    S: tower::Service<Request, Response = R, Error = lambda_http::Error>,
+    S::Future: Send + 'static,
+    R: IntoResponse,
+{
+    type Response = Response<Body>;
+    type Error = lambda_http::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, lambda_http::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let span = span(&req);
+        let start = Instant::now();
+
+        let fut = {
+            let _entered = span.enter();
+            self.service.call(req)
+        };
+
+        let outcome = span.clone();
+        Box::pin(
+            async move {
+                let result = fut.await.map(IntoResponse::into_response);
+
+                outcome.record("latency_ms", start.elapsed().as_millis() as u64);
+                match &result {
+                    Ok(response) => {
+                        outcome.record("http.status_code", response.status().as_u16());
+                    }
+                    Err(e) => {
+                        ::tracing::error!(error = %e, "invocation failed");
+                    }
+                }
+
+                result
+            }
+            .instrument(span),
+        )
+    }
+}
+
+fn span(req: &Request) -> Span {
+    let context = req.extensions().get::<lambda_runtime::Context>();
+
+    // The local server has no invocation context, API Gateway has its own ids
+    let request_id = context
+        .map(|context| context.request_id.as_str())
+        .filter(|id| !id.is_empty())
+        .or_else(|| match req.extensions().get::<RequestContext>()? {
+            RequestContext::ApiGatewayV1(ctx) => ctx.request_id.as_deref(),
+            RequestContext::ApiGatewayV2(ctx) => ctx.request_id.as_deref(),
+            RequestContext::WebSocket(ctx) => ctx.request_id.as_deref(),
+            RequestContext::Alb(_) => None,
+        });
+    let xray_trace_id = context
+        .map(|context| context.xray_trace_id.as_str())
+        .filter(|id| !id.is_empty());
+
+    info_span!(
+        "lambda_invocation",
+        request_id = request_id.unwrap_or_default(),
+        xray_trace_id = xray_trace_id.unwrap_or_default(),
+        http.method = %req.method(),
+        http.path = req.uri().path(),
+        event_source = event_source(req),
+        http.status_code = Empty,
+        latency_ms = Empty,
+    )
+}
+
+/// Name of the event source, as used by the `--source` flag of the local server.
+fn event_source(req: &Request) -> &'static str {
+    match req.extensions().get::<RequestContext>() {
+        Some(RequestContext::Alb(_)) => "alb",
+        Some(RequestContext::ApiGatewayV1(_)) => "apigw-v1",
+        Some(RequestContext::ApiGatewayV2(ctx)) => {
+            let is_function_url = ctx
+                .domain_name
+                .as_deref()
+                .is_some_and(|domain| domain.contains(".lambda-url."));
+
+            if is_function_url {
+                "url"
+            } else {
+                "apigw-v2"
+            }
+        }
+        Some(RequestContext::WebSocket(_)) => "websocket",
+        None => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceLayer;
+    use ::tracing::field::{Field, Visit};
+    use ::tracing::span::{Attributes, Id, Record};
+    use ::tracing::{Event, Metadata, Subscriber};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tower::layer::Layer;
+    use tower::Service;
+
+    /// Keeps the fields of the last span.
+    #[derive(Clone, Default)]
+    struct Fields(Arc<Mutex<HashMap<String, String>>>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    impl Subscriber for Fields {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            span.record(&mut self.clone());
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &Id, values: &Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    async fn created(
+        _req: lambda_http::Request,
+    ) -> Result<lambda_http::Response<lambda_http::Body>, lambda_http::Error> {
+        let response = lambda_http::Response::builder()
+            .status(201)
+            .body(lambda_http::Body::Empty)?;
+
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn span_fields() {
+        let fields = Fields::default();
+        let _guard = ::tracing::subscriber::set_default(fields.clone());
+
+        let mut request =
+            lambda_http::request::from_str(include_str!("../tests/fixtures/function-url.json"))
+                .unwrap();
+        let mut context = lambda_runtime::Context::default();
+        context.request_id = "8476a536-e9f4-11e8-9739-2dfe598c3fcd".to_string();
+        context.xray_trace_id = "Root=1-5759e988-bd862e3fe1be46a994272793".to_string();
+        request.extensions_mut().insert(context);
+
+        let mut service = TraceLayer::new().layer(tower::service_fn(created));
+        let response = service.call(request).await.unwrap();
+
+        assert_eq!(response.status(), 201);
+
+        let fields = fields.0.lock().unwrap();
+        assert_eq!(fields["request_id"], "8476a536-e9f4-11e8-9739-2dfe598c3fcd");
+        assert_eq!(
+            fields["xray_trace_id"],
+            "Root=1-5759e988-bd862e3fe1be46a994272793"
+        );
+        assert_eq!(fields["http.method"], "GET");
+        assert_eq!(fields["http.path"], "/echo");
+        assert_eq!(fields["event_source"], "url");
+        assert_eq!(fields["http.status_code"], "201");
+        assert!(fields.contains_key("latency_ms"));
+    }
+}
@@ -0,0 +1,265 @@
+//! Reconstruction of the request URI seen by the frameworks.
+//!
+//! `lambda_http` builds the request URI out of the path of the event and keeps the
+//! query string parameters on the side, in an extension. On top of that, each event
+//! source encodes those parameters differently: ALB forwards them as they were sent,
+//! percent-encoded, while API Gateway decodes them. This module puts everything back
+//! together into an absolute URI with a consistently encoded query string.
+
+use crate::connection::ConnectionInfo;
+use http::uri::{Authority, PathAndQuery, Scheme, Uri};
+use lambda_http::request::RequestContext;
+use lambda_http::RequestExt;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Characters which are left as is in a query component, as defined by RFC 3986.
+const QUERY_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Rewrite the request URI to be absolute, and to contain the query string parameters
+/// of the event, percent-encoded the same way regardless of the event source.
+pub(crate) fn normalize(req: &mut lambda_http::Request, info: &ConnectionInfo) {
+    let uri = req.uri();
+    let parameters = req.query_string_parameters();
+
+    // API Gateway v2 gives us the raw query string, which `lambda_http` already put in
+    // the uri. It is exactly what the client sent, so there is nothing to rebuild then.
+    // The other sources only have the parameters, which `lambda_http` form-encodes into
+    // the uri without knowing whether they were already encoded, so those are rebuilt.
+    // Requests which did not come from an event (local server, tests) are kept as is.
+    let query = match req.extensions().get::<RequestContext>() {
+        None | Some(RequestContext::ApiGatewayV2(_)) => uri.query().map(str::to_owned),
+        Some(_) if parameters.is_empty() => uri.query().map(str::to_owned),
+        Some(context) => {
+            let already_encoded = matches!(context, RequestContext::Alb(_));
+
+            let mut pairs: Vec<(&str, &str)> = parameters.iter().collect();
+            // Parameters come out of a map, sort them to get the same uri every time.
+            // The sort is stable, so repeated parameters keep their order.
+            pairs.sort_by_key(|(name, _)| *name);
+
+            let pairs: Vec<String> = pairs
+                .into_iter()
+                .map(|(name, value)| {
+                    format!(
+                        "{}={}",
+                        encode(name, already_encoded),
+                        encode(value, already_encoded)
+                    )
+                })
+                .collect();
+
+            Some(pairs.join("&"))
+        }
+    };
+    let query = query.filter(|query| !query.is_empty());
+
+    let path_and_query = match query {
+        Some(query) => format!("{}?{}", uri.path(), query),
+        None => uri.path().to_owned(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+
+    if let Ok(path_and_query) = PathAndQuery::try_from(path_and_query) {
+        parts.path_and_query = Some(path_and_query);
+    }
+
+    if parts.authority.is_none() {
+        parts.authority = info
+            .host
+            .as_deref()
+            .and_then(|host| Authority::try_from(host).ok());
+    }
+
+    if parts.authority.is_some() {
+        parts.scheme = Scheme::try_from(info.scheme.as_str()).ok();
+    }
+
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+}
+
+fn encode(component: &str, already_encoded: bool) -> String {
+    if already_encoded {
+        // ALB forwards the query as sent by browsers, form-encoded, with `+` for spaces
+        let component = component.replace('+', " ");
+        let decoded = percent_decode_str(&component).decode_utf8_lossy();
+
+        utf8_percent_encode(&decoded, QUERY_COMPONENT).to_string()
+    } else {
+        utf8_percent_encode(component, QUERY_COMPONENT).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::ConnectionInfo;
+
+    fn normalized(event: &str) -> String {
+        let mut req = lambda_http::request::from_str(event).unwrap();
+        let info = ConnectionInfo::from_request(&req);
+
+        super::normalize(&mut req, &info);
+
+        req.uri().to_string()
+    }
+
+    #[test]
+    fn alb_query_is_decoded_then_encoded() {
+        let event = r#"{
+            "requestContext": {
+                "elb": {
+                    "targetGroupArn": "arn:aws:elasticloadbalancing:region:123456789012:targetgroup/my-target-group/6d0ecf831eec9f09"
+                }
+            },
+            "httpMethod": "GET",
+            "path": "/hey",
+            "queryStringParameters": { "name": "Jane%20Doe" },
+            "headers": {
+                "host": "lambda-846800462-us-east-2.elb.amazonaws.com",
+                "x-forwarded-proto": "http"
+            },
+            "isBase64Encoded": false,
+            "body": ""
+        }"#;
+
+        assert_eq!(
+            normalized(event),
+            "http://lambda-846800462-us-east-2.elb.amazonaws.com/hey?name=Jane%20Doe"
+        );
+    }
+
+    #[test]
+    fn alb_plus_is_a_space() {
+        let event = r#"{
+            "requestContext": {
+                "elb": {
+                    "targetGroupArn": "arn:aws:elasticloadbalancing:region:123456789012:targetgroup/my-target-group/6d0ecf831eec9f09"
+                }
+            },
+            "httpMethod": "GET",
+            "path": "/hey",
+            "queryStringParameters": { "q": "a+b%2Bc%20d" },
+            "headers": { "host": "lambda-846800462-us-east-2.elb.amazonaws.com" },
+            "isBase64Encoded": false,
+            "body": ""
+        }"#;
+
+        assert_eq!(
+            normalized(event),
+            "https://lambda-846800462-us-east-2.elb.amazonaws.com/hey?q=a%20b%2Bc%20d"
+        );
+    }
+
+    #[test]
+    fn query_is_kept_without_context() {
+        let mut req = http::Request::get("https://example.com/?name=Jane&tag=b&tag=a")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+        let info = ConnectionInfo::from_request(&req);
+
+        super::normalize(&mut req, &info);
+
+        assert_eq!(
+            req.uri().to_string(),
+            "https://example.com/?name=Jane&tag=b&tag=a"
+        );
+    }
+
+    /// API Gateway REST event captured from a real invocation, with the given query
+    /// string parameters.
+    fn api_gateway_v1_event(multi_value_query: &str) -> String {
+        format!(
+            r#"{{
+            "resource": "/{{proxy+}}",
+            "path": "/hey",
+            "httpMethod": "GET",
+            "headers": {{
+                "Host": "wt6mne2s9k.execute-api.us-west-2.amazonaws.com",
+                "X-Forwarded-For": "192.168.100.1, 192.168.1.1",
+                "X-Forwarded-Port": "443",
+                "X-Forwarded-Proto": "https"
+            }},
+            "multiValueQueryStringParameters": {},
+            "pathParameters": {{ "proxy": "hey" }},
+            "stageVariables": null,
+            "requestContext": {{
+                "accountId": "123456789012",
+                "resourceId": "us4z18",
+                "stage": "test",
+                "requestId": "41b45ea3-70b5-11e6-b7bd-69b5aaebc7d9",
+                "requestTimeEpoch": 1583798639428,
+                "identity": {{ "sourceIp": "192.168.100.1" }},
+                "resourcePath": "/{{proxy+}}",
+                "httpMethod": "GET",
+                "apiId": "wt6mne2s9k"
+            }},
+            "body": null,
+            "isBase64Encoded": false
+        }}"#,
+            multi_value_query
+        )
+    }
+
+    #[test]
+    fn api_gateway_v1_query_is_encoded() {
+        let event = api_gateway_v1_event(r#"{ "name": ["Jane Doe"] }"#);
+
+        assert_eq!(
+            normalized(&event),
+            "https://wt6mne2s9k.execute-api.us-west-2.amazonaws.com/test/hey?name=Jane%20Doe"
+        );
+    }
+
+    #[test]
+    fn query_is_sorted_by_name() {
+        let event = api_gateway_v1_event(r#"{ "tag": ["b", "a&c"], "name": ["Jane"] }"#);
+
+        // Repeated parameters keep the order of the event
+        assert_eq!(
+            normalized(&event),
+            "https://wt6mne2s9k.execute-api.us-west-2.amazonaws.com/test/hey?name=Jane&tag=b&tag=a%26c"
+        );
+    }
+
+    #[test]
+    fn api_gateway_v2_raw_query_is_kept() {
+        let event = r#"{
+            "version": "2.0",
+            "routeKey": "$default",
+            "rawPath": "/hey",
+            "rawQueryString": "name=Jane%20Doe&tag=a%26b",
+            "headers": { "host": "id.lambda-url.us-east-1.on.aws" },
+            "queryStringParameters": { "name": "Jane Doe", "tag": "a&b" },
+            "requestContext": {
+                "accountId": "123456789012",
+                "apiId": "id",
+                "domainName": "id.lambda-url.us-east-1.on.aws",
+                "domainPrefix": "id",
+                "http": {
+                    "method": "GET",
+                    "path": "/hey",
+                    "protocol": "HTTP/1.1",
+                    "sourceIp": "203.0.113.8",
+                    "userAgent": "agent"
+                },
+                "requestId": "id",
+                "routeKey": "$default",
+                "stage": "$default",
+                "time": "12/Mar/2020:19:03:58 +0000",
+                "timeEpoch": 1583348638390
+            },
+            "isBase64Encoded": false
+        }"#;
+
+        assert_eq!(
+            normalized(event),
+            "https://id.lambda-url.us-east-1.on.aws/hey?name=Jane%20Doe&tag=a%26b"
+        );
+    }
+}
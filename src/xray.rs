@@ -0,0 +1,90 @@
+//! Propagation of the X-Ray trace header to the frameworks.
+//!
+//! Lambda gives the trace id of an invocation to the runtime, not in the request
+//! headers. `lambda_runtime` already exposes it in the `_X_AMZN_TRACE_ID` environment
+//! variable, like other runtimes do. Putting it in the `X-Amzn-Trace-Id` header of the
+//! framework request as well lets middleware and outbound clients propagate it like
+//! they would behind a load balancer.
+
+use http::header::HeaderValue;
+
+pub(crate) const X_AMZN_TRACE_ID: &str = "x-amzn-trace-id";
+
+/// Set the `X-Amzn-Trace-Id` header of the request from the invocation context, unless
+/// the event source already set it.
+pub(crate) fn propagate(req: &mut lambda_http::Request) {
+    if req.headers().contains_key(X_AMZN_TRACE_ID) {
+        return;
+    }
+
+    let trace_id = req
+        .extensions()
+        .get::<lambda_runtime::Context>()
+        .and_then(|context| HeaderValue::from_str(&context.xray_trace_id).ok())
+        .filter(|id| !id.is_empty());
+
+    if let Some(value) = trace_id {
+        req.headers_mut().insert(X_AMZN_TRACE_ID, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{propagate, X_AMZN_TRACE_ID};
+
+    fn request(header: Option<&str>, trace_id: &str) -> lambda_http::Request {
+        let mut builder = http::Request::get("/");
+        if let Some(header) = header {
+            builder = builder.header(X_AMZN_TRACE_ID, header);
+        }
+
+        let mut req = builder.body(lambda_http::Body::Empty).unwrap();
+        let mut context = lambda_runtime::Context::default();
+        context.xray_trace_id = trace_id.to_string();
+        req.extensions_mut().insert(context);
+
+        req
+    }
+
+    #[test]
+    fn propagate_trace_id_from_context() {
+        let root = "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1";
+
+        let mut req = request(None, root);
+        propagate(&mut req);
+
+        assert_eq!(req.headers()[X_AMZN_TRACE_ID], root);
+    }
+
+    #[test]
+    fn keep_existing_header() {
+        // The load balancer already set the header
+        let mut req = request(
+            Some("Root=1-5bdb40ca-556d8b0c50dc66f0511bf520"),
+            "Root=1-other",
+        );
+        propagate(&mut req);
+
+        assert_eq!(
+            req.headers()[X_AMZN_TRACE_ID],
+            "Root=1-5bdb40ca-556d8b0c50dc66f0511bf520"
+        );
+    }
+
+    #[test]
+    fn no_trace_id_without_context() {
+        // Like the local server, which has no invocation to trace
+        let mut req = http::Request::get("/")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+        propagate(&mut req);
+
+        assert!(!req.headers().contains_key(X_AMZN_TRACE_ID));
+
+        // Nor with an empty context
+        let mut req = request(None, "");
+        propagate(&mut req);
+
+        assert!(!req.headers().contains_key(X_AMZN_TRACE_ID));
+    }
+}
@@ -0,0 +1,276 @@
+//! Run every event of `tests/fixtures` through the adapters, and check that the
+//! frameworks see the same request regardless of the event source.
+//!
+//! The fixtures are events captured from real invocations, taken from the test data
+//! of `lambda_http` and `aws_lambda_events` and from the function URL documentation,
+//! with only the method, path, query and body changed for each case. Hand-written
+//! events tend to miss fields that `lambda_http` requires.
+//!
+//! With the `testing` feature, the payload sent back to AWS is checked as well.
+#![cfg(any(feature = "actix", feature = "axum"))]
+
+use std::net::SocketAddr;
+
+/// Fixture name, and the request as seen by the echo handler.
+const CASES: &[(&str, &str)] = &[
+    (
+        "alb.json",
+        "GET https://lambda-846800462-us-east-2.elb.amazonaws.com/echo?name=Jane%20Doe
+peer: Some(72.21.198.66:0)
+content-type: Some(\"text/plain\")
+body: \"request_body\"",
+    ),
+    (
+        // Browsers form-encode queries, ALB forwards them as they were sent
+        "alb-form-encoded.json",
+        "GET https://lambda-846800462-us-east-2.elb.amazonaws.com/echo?name=Jane%20Doe&q=rust%20lambda
+peer: Some(72.21.198.66:0)
+content-type: Some(\"text/plain\")
+body: \"request_body\"",
+    ),
+    (
+        "alb-multi-value.json",
+        "POST https://lambda-846800462-us-east-2.elb.amazonaws.com/echo?tag=a&tag=b%26c
+peer: Some(72.21.198.66:0)
+content-type: Some(\"text/plain\")
+body: \"request_body\"",
+    ),
+    (
+        "apigw-v1.json",
+        "POST https://gy415nuibc.execute-api.us-east-1.amazonaws.com/echo?name=Jane%20Doe
+peer: Some(192.168.196.186:0)
+content-type: Some(\"application/json\")
+body: \"{\\r\\n\\t\\\"a\\\": 1\\r\\n}\"",
+    ),
+    (
+        "apigw-v1-base64.json",
+        "PUT https://wt6mne2s9k.execute-api.us-west-2.amazonaws.com/echo
+peer: Some(192.168.100.1:0)
+content-type: Some(\"application/octet-stream\")
+body: [0, 1, 2, 255]",
+    ),
+    (
+        // The captured event has a placeholder instead of the source ip
+        "apigw-v2.json",
+        "POST https://id.execute-api.us-east-1.amazonaws.com/echo?name=Jane%20Doe&tag=a&tag=b
+peer: None
+content-type: Some(\"text/plain\")
+body: \"Hello from Lambda\"",
+    ),
+    (
+        "apigw-v2-base64.json",
+        "PUT https://aaaaaaaaaa.execute-api.us-west-2.amazonaws.com/echo
+peer: Some(1.2.3.4:0)
+content-type: Some(\"application/octet-stream\")
+body: [0, 1, 2, 255]",
+    ),
+    (
+        "function-url.json",
+        "GET https://abcdefghijklmnop.lambda-url.us-west-2.on.aws/echo?name=Jane%20Doe
+peer: Some(123.123.123.123:0)
+content-type: None
+body: \"\"",
+    ),
+];
+
+fn event(name: &str) -> String {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+
+    std::fs::read_to_string(&path).expect("fixture should exist")
+}
+
+fn fixture(name: &str) -> lambda_http::Request {
+    lambda_http::request::from_str(&event(name)).expect("fixture should be a valid event")
+}
+
+/// Textual snapshot of what a handler received.
+fn describe(
+    method: &str,
+    uri: &str,
+    peer: Option<SocketAddr>,
+    content_type: Option<&str>,
+    body: &[u8],
+) -> String {
+    let body = match std::str::from_utf8(body) {
+        Ok(text) => format!("{:?}", text),
+        Err(_) => format!("{:?}", body),
+    };
+
+    format!(
+        "{} {}\npeer: {:?}\ncontent-type: {:?}\nbody: {}",
+        method, uri, peer, content_type, body
+    )
+}
+
+fn assert_echo(name: &str, expected: &str, response: lambda_http::Response<lambda_http::Body>) {
+    assert_eq!(response.status(), http::StatusCode::OK, "{}", name);
+    assert_eq!(response.headers()["content-type"], "text/plain", "{}", name);
+
+    let body = match response.into_body() {
+        lambda_http::Body::Binary(bytes) => String::from_utf8(bytes).unwrap(),
+        other => panic!("{}: unexpected body {:?}", name, other),
+    };
+
+    assert_eq!(body, expected, "{}", name);
+}
+
+/// Snapshot of the payload sent back to AWS for the echo response, which only depends
+/// on the event source. Framework bodies are bytes, so they are always base64 encoded.
+#[cfg(feature = "testing")]
+fn assert_payload(name: &str, expected: &str, payload: serde_json::Value) {
+    use serde_json::json;
+
+    let body = base64::encode(expected);
+    let snapshot = if name.starts_with("alb") {
+        json!({
+            "statusCode": 200,
+            "statusDescription": "200 OK",
+            "headers": { "content-type": "text/plain" },
+            "multiValueHeaders": { "content-type": ["text/plain"] },
+            "body": body,
+            "isBase64Encoded": true
+        })
+    } else if name.starts_with("apigw-v1") {
+        json!({
+            "statusCode": 200,
+            "headers": { "content-type": "text/plain" },
+            "multiValueHeaders": { "content-type": ["text/plain"] },
+            "body": body,
+            "isBase64Encoded": true
+        })
+    } else {
+        json!({
+            "statusCode": 200,
+            "headers": { "content-type": "text/plain" },
+            "multiValueHeaders": { "content-type": ["text/plain"] },
+            "body": body,
+            "isBase64Encoded": true,
+            "cookies": []
+        })
+    };
+
+    assert_eq!(payload, snapshot, "{}", name);
+}
+
+#[cfg(feature = "actix")]
+mod actix {
+    use actix_web::{web, HttpRequest, HttpResponse};
+    use aws_lambda_http_interop::actix::ActixTowerService;
+    use aws_lambda_http_interop::path::BasePath;
+    #[cfg(feature = "testing")]
+    use aws_lambda_http_interop::testing;
+    use tower::Service;
+
+    async fn echo(req: HttpRequest, body: web::Bytes) -> HttpResponse {
+        let content_type = req
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok());
+
+        let description = super::describe(
+            req.method().as_str(),
+            &req.uri().to_string(),
+            req.peer_addr(),
+            content_type,
+            &body,
+        );
+
+        HttpResponse::Ok()
+            .content_type("text/plain")
+            .body(description)
+    }
+
+    async fn service() -> ActixTowerService {
+        let factory = || actix_web::App::new().route("/echo", web::to(echo));
+
+        aws_lambda_http_interop::actix::service(factory)
+            .await
+            .unwrap()
+            .with_base_path(BasePath::Stage)
+    }
+
+    #[tokio::test]
+    async fn fixtures_through_actix() {
+        for (name, expected) in super::CASES {
+            let mut service = service().await;
+
+            futures::future::poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .unwrap();
+            let response = service.call(super::fixture(name)).await.unwrap();
+
+            super::assert_echo(name, expected, response);
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn payloads_through_actix() {
+        for (name, expected) in super::CASES {
+            let payload = testing::invoke(&mut service().await, &super::event(name))
+                .await
+                .unwrap();
+
+            super::assert_payload(name, expected, payload);
+        }
+    }
+}
+
+#[cfg(feature = "axum")]
+mod axum {
+    use aws_lambda_http_interop::axum::AxumService;
+    use aws_lambda_http_interop::path::BasePath;
+    #[cfg(feature = "testing")]
+    use aws_lambda_http_interop::testing;
+    use axum::body::Bytes;
+    use axum::extract::ConnectInfo;
+    use axum::http::{HeaderMap, Method, Uri};
+    use axum::response::{Headers, IntoResponse};
+    use std::net::SocketAddr;
+    use tower::Service;
+
+    async fn echo(
+        method: Method,
+        uri: Uri,
+        peer: Option<ConnectInfo<SocketAddr>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> impl IntoResponse {
+        let content_type = headers.get("content-type").and_then(|v| v.to_str().ok());
+        let peer = peer.map(|ConnectInfo(addr)| addr);
+
+        let description =
+            super::describe(method.as_str(), &uri.to_string(), peer, content_type, &body);
+
+        (Headers([("content-type", "text/plain")]), description)
+    }
+
+    async fn service() -> AxumService {
+        let router = axum::Router::new().route("/echo", axum::routing::any(echo));
+
+        aws_lambda_http_interop::axum::service(router)
+            .await
+            .with_base_path(BasePath::Stage)
+    }
+
+    #[tokio::test]
+    async fn fixtures_through_axum() {
+        for (name, expected) in super::CASES {
+            let response = service().await.call(super::fixture(name)).await.unwrap();
+
+            super::assert_echo(name, expected, response);
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn payloads_through_axum() {
+        for (name, expected) in super::CASES {
+            let payload = testing::invoke(&mut service().await, &super::event(name))
+                .await
+                .unwrap();
+
+            super::assert_payload(name, expected, payload);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Run `actix::run` against the Runtime API emulator, like it would run on lambda.
+#![cfg(all(feature = "emulator", feature = "actix"))]
+
+use actix_web::{web, App, HttpRequest, HttpResponse};
+use aws_lambda_http_interop::emulator::RuntimeEmulator;
+use serde_json::Value;
+
+async fn hello(req: HttpRequest) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain")
+        .body(format!("{} {}", req.method(), req.path()))
+}
+
+#[tokio::test]
+async fn actix_run_end_to_end() {
+    let emulator = RuntimeEmulator::start().await.unwrap();
+    emulator.set_env();
+
+    // `actix::run` is `!Send`, like `lambda_http::run`, so it gets a thread and a runtime
+    // of its own, as it would in the `main` of a function.
+    std::thread::spawn(|| {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // Fails once the emulator is dropped at the end of the test
+        let _ = runtime.block_on(aws_lambda_http_interop::actix::run(|| {
+            App::new().route("/echo", web::to(hello))
+        }));
+    });
+
+    let response = emulator
+        .invoke(include_str!("fixtures/function-url.json"))
+        .await
+        .unwrap();
+    let response: Value = serde_json::from_slice(&response).unwrap();
+
+    assert_eq!(response["statusCode"], 200);
+    assert_eq!(
+        response["multiValueHeaders"]["content-type"][0],
+        "text/plain"
+    );
+    // actix bodies are bytes, always sent base64 encoded
+    assert_eq!(response["isBase64Encoded"], true);
+    assert_eq!(
+        base64::decode(response["body"].as_str().unwrap()).unwrap(),
+        b"GET /echo"
+    );
+}
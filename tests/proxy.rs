@@ -0,0 +1,125 @@
+//! Send HTTP requests through the proxy to a function served by `lambda_http::run`,
+//! which makes them go through the real event and response serialization.
+#![cfg(feature = "proxy")]
+
+use aws_lambda_http_interop::emulator::RuntimeEmulator;
+use aws_lambda_http_interop::proxy::{EventSource, Proxy};
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+use tower::Service;
+
+async fn echo(
+    req: lambda_http::Request,
+) -> Result<lambda_http::Response<lambda_http::Body>, lambda_http::Error> {
+    let response = lambda_http::Response::builder()
+        .header("content-type", "application/octet-stream")
+        .header("set-cookie", "session=abc")
+        .body(req.into_body())?;
+
+    Ok(response)
+}
+
+/// The runtime finds the emulator through the environment, which is shared by the tests.
+static ENV: Mutex<()> = Mutex::const_new(());
+
+/// Start a function serving `echo` against a new emulator, holding the environment
+/// until the test is done.
+async fn start_function() -> (Arc<RuntimeEmulator>, MutexGuard<'static, ()>) {
+    let guard = ENV.lock().await;
+    let emulator = Arc::new(RuntimeEmulator::start().await.unwrap());
+
+    // `lambda_http::run` is `!Send`, so it gets a thread and a runtime of its own, as it
+    // would in the `main` of a function
+    let env = emulator.env();
+    std::thread::spawn(move || {
+        for (name, value) in env {
+            std::env::set_var(name, value);
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // Fails once the emulator is dropped at the end of the test
+        let _ = runtime.block_on(lambda_http::run(tower::service_fn(echo)));
+    });
+
+    (emulator, guard)
+}
+
+#[tokio::test]
+async fn proxy_every_event_source() {
+    let (emulator, _env) = start_function().await;
+
+    let sources = [
+        EventSource::Alb,
+        EventSource::ApiGatewayV1,
+        EventSource::ApiGatewayV2,
+        EventSource::FunctionUrl,
+    ];
+
+    for source in sources {
+        let mut proxy = Proxy::new(source, emulator.clone());
+
+        let request = hyper::Request::put("/echo?name=Jane%20Doe")
+            .body(hyper::Body::from(vec![0u8, 1, 2, 255]))
+            .unwrap();
+
+        futures::future::poll_fn(|cx| proxy.poll_ready(cx))
+            .await
+            .unwrap();
+        let response = proxy.call(request).await.unwrap();
+
+        assert_eq!(response.status(), 200, "{:?}", source);
+        assert_eq!(
+            response.headers()["set-cookie"],
+            "session=abc",
+            "{:?}",
+            source
+        );
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], &[0, 1, 2, 255], "{:?}", source);
+    }
+}
+
+#[cfg(feature = "testing")]
+#[tokio::test]
+async fn replay_recorded_invocations() {
+    let dir = std::env::temp_dir().join(format!("proxy-recordings-{}", std::process::id()));
+    let (emulator, _env) = start_function().await;
+
+    let sources = [
+        EventSource::Alb,
+        EventSource::ApiGatewayV1,
+        EventSource::ApiGatewayV2,
+        EventSource::FunctionUrl,
+    ];
+
+    for source in sources {
+        let mut proxy = Proxy::new(source, emulator.clone()).with_recording(&dir);
+
+        for body in [&b"hello"[..], &[0, 1, 2, 255], &[]] {
+            let request = hyper::Request::post("/echo?name=Jane%20Doe")
+                .header("host", "localhost:3000")
+                .body(hyper::Body::from(body.to_vec()))
+                .unwrap();
+
+            let response = proxy.call(request).await.unwrap();
+            assert_eq!(response.status(), 200, "{:?}", source);
+        }
+    }
+
+    // The same function, in-process this time, must answer exactly what was recorded
+    let mut service = tower::service_fn(echo);
+    let mismatches = aws_lambda_http_interop::testing::replay(&mut service, &dir)
+        .await
+        .unwrap();
+    let recordings = std::fs::read_dir(&dir).unwrap().count();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(recordings, 12);
+    assert!(mismatches.is_empty(), "{:#?}", mismatches);
+}